@@ -1,13 +1,19 @@
 #import bevy_pbr::utils::{rand_vec2f, rand_range_u};
-#import types::{settings, particles, counter, sorted_indices};
-#import functions::{closest_wrapped_other_position, get_matrix_value, acceleration, cell_index, cell_count, surrounding_cells};
+#import types::{settings, particles, counter, sorted_indices, particles_copy, erase_count, particle_fields, obstacles, species, next_colors, probe};
+#import functions::{other_position, wall_acceleration, obstacle_acceleration, force_field_acceleration, brownian_kick, reactions_in_range, react, obstacle_distance, brush_acceleration, get_matrix_value, get_radii, acceleration, cell_index, cell_count, surrounding_cells, NO_CELL, BOUNDARY_WRAP, BOUNDARY_REFLECT, BOUNDARY_SOFT_WALL, OBSTACLE_COLLIDE};
 
 const WORKGROUP_SIZE: u32 = 64;
 
+// The particles past the count left after erasing are skipped until
+// the count is read back, so erased particles stop affecting the others right away.
+fn live_particle_count() -> u32 {
+    return min(settings.particle_count, atomicLoad(&erase_count));
+}
+
 // counter[ci] will countain the amount of particles in cell index ci
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn count_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    if (global_id.x >= settings.particle_count) {
+    if (global_id.x >= live_particle_count()) {
         return;
     }
 
@@ -25,7 +31,7 @@ fn cell_offsets() {
         counter[i] = counter[i] + counter[i - 1];
     }
     let total_cells = cell_count();
-    counter[total_cells] = settings.particle_count;
+    counter[total_cells] = live_particle_count();
 }
 
 // counter[ci] will be the starting index of the particles of cell index
@@ -33,7 +39,7 @@ fn cell_offsets() {
 // Which also means that (counter[ci + 1]).max(total_particle_count) is the end.
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn sort_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    if (global_id.x >= settings.particle_count) {
+    if (global_id.x >= live_particle_count()) {
         return;
     }
 
@@ -49,12 +55,13 @@ fn initialize_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    var index = settings.initialized_particles + global_id.x;
+    let index = settings.initialized_particles + global_id.x;
+    var seed = settings.seed + index;
 
     let p = &particles.particles[index];
     (*p).velocity = vec2<f32>(0.);
-    (*p).position = (2. * rand_vec2f(&index) - 1.) * settings.bounds;
-    (*p).color = rand_range_u(settings.color_count, &index);
+    (*p).position = (2. * rand_vec2f(&seed) - 1.) * settings.bounds;
+    (*p).color = rand_range_u(settings.color_count, &seed);
 }
 
 @compute @workgroup_size(WORKGROUP_SIZE)
@@ -83,7 +90,7 @@ fn randomize_colors(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn update_velocity(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    if (global_id.x >= settings.particle_count) {
+    if (global_id.x >= live_particle_count()) {
         return;
     }
 
@@ -91,14 +98,23 @@ fn update_velocity(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     let particle = particles.particles[index];
     let particle_ref = &particles.particles[index];
-   (*particle_ref).velocity *= pow(0.5, settings.delta_time / settings.velocity_half_life);
+    let particle_species = species[particle.color];
+    (*particle_ref).velocity *= pow(0.5, settings.delta_time / particle_species.velocity_half_life);
 
     var surrounding = surrounding_cells(particle.position);
 
     let max_attractions_per_cell = max(1u, u32(f32(settings.max_attractions) / 9.));
 
+    var force = vec2<f32>(0.);
+    var neighbours = 0u;
+    // see reactions_in_range
+    var in_range = 0u;
+
     for (var j = 0u; j < 9; j++) {
         let ci = surrounding[j];
+        if (ci == NO_CELL) {
+            continue;
+        }
         let start = counter[ci];
         var end = counter[ci + 1];
 
@@ -110,42 +126,158 @@ fn update_velocity(@builtin(global_invocation_id) global_id: vec3<u32>) {
         for (var i = start; i < end; i++) {
             let pi = sorted_indices[i];
             let other = particles.particles[pi];
-            let other_position = closest_wrapped_other_position(particle.position, other.position, settings.bounds);
+            let other_position = other_position(particle.position, other.position);
 
             let relative_position = other_position - particle.position;
             let distance_squared = dot(relative_position, relative_position);
 
-            if distance_squared == 0. || distance_squared > settings.max_distance * settings.max_distance {
+            if (settings.reaction_count > 0u && pi != index) {
+                in_range |= reactions_in_range(particle.color, other.color, distance_squared);
+            }
+
+            let radii = get_radii(particle.color, other.color);
+            let max_radius = radii.y;
+
+            if distance_squared == 0. || distance_squared > max_radius * max_radius {
                 continue;
             }
 
             let attraction = get_matrix_value(particle.color, other.color);
 
-            let a = acceleration(settings.min_distance / settings.max_distance, relative_position / settings.max_distance, attraction);
+            let a = acceleration(radii.x / max_radius, relative_position / max_radius, attraction);
 
-            (*particle_ref).velocity += a * settings.max_distance * settings.force_factor * settings.delta_time;
+            force += a * max_radius * settings.force_factor;
+            neighbours += 1u;
         }
     }
+
+    if (settings.boundary_mode == BOUNDARY_SOFT_WALL) {
+        let a = wall_acceleration(particle.position);
+        force += a * settings.max_distance * settings.force_factor;
+    }
+
+    if (settings.obstacle_count > 0u) {
+        let a = obstacle_acceleration(particle.position);
+        force += a * settings.max_distance * settings.force_factor;
+    }
+
+    force += brush_acceleration(particle.position);
+    (*particle_ref).velocity += force / particle_species.mass * settings.delta_time;
+    // force fields accelerate all species the same, like gravity
+    (*particle_ref).velocity += force_field_acceleration(particle.position) * settings.delta_time;
+    if (settings.temperature > 0.) {
+        // heavier particles jiggle less, like molecules of the same temperature
+        (*particle_ref).velocity += brownian_kick(index) / sqrt(particle_species.mass);
+    }
+    let speed = length((*particle_ref).velocity);
+    if (speed > particle_species.max_velocity) {
+        (*particle_ref).velocity *= particle_species.max_velocity / speed;
+    }
+    particle_fields[index] = vec2<f32>(f32(neighbours), length(force));
+    if (settings.reaction_count > 0u) {
+        next_colors[index] = react(index, particle.color, in_range);
+    }
 }
 
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn update_position(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    if (global_id.x >= settings.particle_count) {
+    if (global_id.x >= live_particle_count()) {
         return;
     }
 
     let particle = &particles.particles[global_id.x];
     (*particle).position += (*particle).velocity * settings.delta_time;
+    if (settings.reaction_count > 0u) {
+        (*particle).color = next_colors[global_id.x];
+    }
+
+    // moving out of the obstacles and bouncing off of them
+    for (var i = 0u; i < settings.obstacle_count; i++) {
+        let obstacle = obstacles[i];
+        if (obstacle.response != OBSTACLE_COLLIDE) {
+            continue;
+        }
+        let d = obstacle_distance(obstacle, (*particle).position);
+        if (d.x < 0.) {
+            (*particle).position -= d.x * d.yz;
+            (*particle).velocity -= 2. * min(dot((*particle).velocity, d.yz), 0.) * d.yz;
+        }
+    }
 
     let p = particles.particles[global_id.x];
-    if p.position.x > settings.bounds.x {
-        (*particle).position.x -= 2. * settings.bounds.x;
-        } else if p.position.x < -settings.bounds.x {
-        (*particle).position.x += 2. * settings.bounds.x;
-    }
-    if p.position.y > settings.bounds.y {
-        (*particle).position.y -= 2. * settings.bounds.y;
-        } else if p.position.y < -settings.bounds.y {
-        (*particle).position.y += 2. * settings.bounds.y;
+    let bounds = settings.bounds;
+    if (settings.boundary_mode == BOUNDARY_WRAP) {
+        if p.position.x > bounds.x {
+            (*particle).position.x -= 2. * bounds.x;
+        } else if p.position.x < -bounds.x {
+            (*particle).position.x += 2. * bounds.x;
+        }
+        if p.position.y > bounds.y {
+            (*particle).position.y -= 2. * bounds.y;
+        } else if p.position.y < -bounds.y {
+            (*particle).position.y += 2. * bounds.y;
+        }
+    } else if (settings.boundary_mode == BOUNDARY_REFLECT) {
+        // mirroring the part that went past the bounds
+        let outside = abs(p.position) > bounds;
+        let reflected = sign(p.position) * 2. * bounds - p.position;
+        (*particle).position = clamp(select(p.position, reflected, outside), -bounds, bounds);
+        (*particle).velocity = select(p.velocity, -p.velocity, outside);
+    } else {
+        // clamp and soft wall, the soft wall should usually push particles back before this
+        let outside = abs(p.position) > bounds;
+        (*particle).position = clamp(p.position, -bounds, bounds);
+        (*particle).velocity = select(p.velocity, vec2<f32>(0.), outside);
+    }
+}
+
+// Moves the particles outside of the eraser to the front of particles.
+// The order of the remaining particles is not preserved.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn erase_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= settings.particle_count) {
+        return;
+    }
+
+    let particle = particles_copy[global_id.x];
+    let relative_position = other_position(particle.position, settings.erase_position) - particle.position;
+    if (dot(relative_position, relative_position) <= settings.erase_radius * settings.erase_radius) {
+        return;
+    }
+
+    let index = atomicAdd(&erase_count, 1u);
+    particles.particles[index] = particle;
+}
+
+// Finds the particle closest to probe.cursor in the cells around it and the cell
+// it was sorted into, so the grid debug overlay only reads back this result.
+@compute @workgroup_size(1)
+fn probe_particle() {
+    let cursor = probe.cursor;
+    var closest = settings.max_distance * settings.max_distance;
+    probe.cell_index = NO_CELL;
+
+    var surrounding = surrounding_cells(cursor);
+    for (var j = 0u; j < 9; j++) {
+        let ci = surrounding[j];
+        if (ci == NO_CELL) {
+            continue;
+        }
+        let start = counter[ci];
+        let end = counter[ci + 1];
+        for (var i = start; i < end; i++) {
+            let index = sorted_indices[i];
+            let position = particles.particles[index].position;
+            let relative_position = position - cursor;
+            let distance_squared = dot(relative_position, relative_position);
+            if (distance_squared <= closest) {
+                closest = distance_squared;
+                probe.position = position;
+                probe.index = index;
+                probe.cell_index = ci;
+                probe.start = start;
+                probe.end = end;
+            }
+        }
     }
 }
@@ -1,5 +1,12 @@
-#import types::{settings, particles, view, counter};
-#import functions::{surrounding_cells, cell_index};
+#import types::{settings, particles, view, counter, colors, particle_fields, species, erase_count, Particle};
+#import functions::{surrounding_cells, cell_index, colormap, srgb_to_linear};
+
+struct ViewParticleSettings {
+    particle_size: f32,
+}
+
+// settings that differ between views, e.g. the particle size of a poster
+@group(1) @binding(0) var<uniform> view_particles: ViewParticleSettings;
 
 struct VertexInput {
     @builtin(vertex_index) index: u32,
@@ -9,6 +16,8 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    // -1 to 1 across the particle
+    @location(1) local: vec2<f32>,
 }
 
 const PI: f32 = 3.14159;
@@ -21,11 +30,20 @@ fn polar_to_cartesian(r: f32, theta: f32) -> vec2<f32> {
 fn vertex(input: VertexInput) -> VertexOutput {
     var out : VertexOutput;
 
+    // left over from erasing until the particle count is read back
+    if (input.instance >= atomicLoad(&erase_count)) {
+        out.position = vec4<f32>(0.);
+        return out;
+    }
+
+    let particle = particles.particles[input.instance];
+    let size = view_particles.particle_size * species[particle.color].size;
+
     var local_position: vec2<f32>;
-    // Square
-    if (settings.shape == 1) {
+    // Square or Glow
+    if (settings.shape == 1 || settings.shape == 2) {
         let index = square_indices[input.index];
-        local_position = settings.particle_size * square_vertices[index];
+        local_position = size * square_vertices[index];
     } else if (settings.shape == 0) {
         if (input.index % 3 == 2) {
             local_position = vec2<f32>(0.);
@@ -34,36 +52,67 @@ fn vertex(input: VertexInput) -> VertexOutput {
             let offset = input.index % 3;
             let angle = 2. * PI * f32(i + offset
             ) / f32(settings.circle_corners);
-            local_position = polar_to_cartesian(settings.particle_size, angle);
+            local_position = polar_to_cartesian(size, angle);
         }
 
     }
 
-    let particle = particles.particles[input.instance];
     let center = particle.position;
 
     let view_position = vec4<f32>(local_position  + center, 0., 1.);
     let clip_position = view.clip_from_world * view_position;
 
     out.position = clip_position;
+    out.local = local_position / size;
 
-    if (settings.rgb == 1u) {
+    if (settings.color_mode != COLOR_SPECIES) {
+        let value = clamp(scalar_field(input.instance, particle), 0., 1.);
+        out.color = vec4<f32>(srgb_to_linear(colormap(value)), 1.);
+    } else if (settings.rgb == 1u) {
         let color_f32 = (f32(particle.color) + settings.time * settings.rgb_speed) % f32(settings.max_color_count);
-        let color_1 = settings.colors[u32(floor(color_f32))];
-        let color_2 = settings.colors[u32(ceil(color_f32)) % settings.max_color_count];
+        let color_1 = colors[u32(floor(color_f32))];
+        let color_2 = colors[u32(ceil(color_f32)) % settings.max_color_count];
         let t = fract(color_f32);
 
         out.color = mix(color_1, color_2, t);
     } else {
-        out.color = settings.colors[particle.color];
+        out.color = colors[particle.color];
     }
 
     return out;
 }
 
+const COLOR_SPECIES: u32 = 0u;
+const COLOR_SPEED: u32 = 1u;
+const COLOR_HEADING: u32 = 2u;
+const COLOR_NEIGHBOURS: u32 = 3u;
+
+// The value of the color mode, 0 to 1 for the usual range
+fn scalar_field(index: u32, particle: Particle) -> f32 {
+    switch (settings.color_mode) {
+        case COLOR_SPEED: {
+            return length(particle.velocity) / settings.color_max;
+        }
+        case COLOR_HEADING: {
+            return atan2(particle.velocity.y, particle.velocity.x) / (2. * PI) + 0.5;
+        }
+        case COLOR_NEIGHBOURS: {
+            return particle_fields[index].x / settings.color_max;
+        }
+        default: {
+            return particle_fields[index].y / settings.color_max;
+        }
+    }
+}
+
 @fragment
-fn fragment(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
-    return color;
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Glow
+    if (settings.shape == 2) {
+        let falloff = max(1. - length(in.local), 0.);
+        return vec4<f32>(in.color.rgb, in.color.a * falloff * falloff);
+    }
+    return in.color;
 }
 
 var<private> square_vertices: array<vec2<f32>, 4> = array<vec2<f32>, 4>(
@@ -1,9 +1,34 @@
 #define_import_path functions
 
-#import types::settings;
+#import types::{settings, interactions, Interaction, obstacles, Obstacle, force_fields, reactions};
 
 const PI: f32 = 3.14159;
 
+const BOUNDARY_WRAP: u32 = 0u;
+const BOUNDARY_REFLECT: u32 = 1u;
+const BOUNDARY_CLAMP: u32 = 2u;
+const BOUNDARY_SOFT_WALL: u32 = 3u;
+
+const OBSTACLE_CIRCLE: u32 = 0u;
+const OBSTACLE_RECTANGLE: u32 = 1u;
+const OBSTACLE_SEGMENT: u32 = 2u;
+
+const OBSTACLE_COLLIDE: u32 = 0u;
+const OBSTACLE_REPEL: u32 = 1u;
+
+const FIELD_GRAVITY: u32 = 0u;
+const FIELD_ATTRACTOR: u32 = 1u;
+const FIELD_VORTEX: u32 = 2u;
+const FIELD_NOISE: u32 = 3u;
+
+const BRUSH_NONE: u32 = 0u;
+const BRUSH_ATTRACT: u32 = 1u;
+const BRUSH_REPEL: u32 = 2u;
+const BRUSH_SWIRL: u32 = 3u;
+
+// Marks a cell in surrounding_cells that doesn't exist
+const NO_CELL: u32 = 0xffffffffu;
+
 fn closest_wrapped_other_position(pos: vec2<f32>, other_pos: vec2<f32>, bounds: vec2<f32>) -> vec2<f32> {
     var other = other_pos;
 
@@ -29,12 +54,233 @@ fn closest_wrapped_other_position(pos: vec2<f32>, other_pos: vec2<f32>, bounds:
     return other;
 }
 
+// The position of other as seen from pos. Only wraps around the bounds
+// if the boundary mode is wrap.
+fn other_position(pos: vec2<f32>, other_pos: vec2<f32>) -> vec2<f32> {
+    if (settings.boundary_mode == BOUNDARY_WRAP) {
+        return closest_wrapped_other_position(pos, other_pos, settings.bounds);
+    }
+    return other_pos;
+}
+
+// Repulsion of the soft walls, relative to max_distance like acceleration.
+// A wall pushes the same way a particle closer than min_distance does.
+fn wall_acceleration(position: vec2<f32>) -> vec2<f32> {
+    let rmin = max(settings.min_distance, 1.);
+    let to_lower = position + settings.bounds;
+    let to_upper = settings.bounds - position;
+    return max(vec2<f32>(0.), 1. - to_lower / rmin) - max(vec2<f32>(0.), 1. - to_upper / rmin);
+}
+
+// The signed distance from the surface of the obstacle, negative inside of it,
+// followed by the direction out of the obstacle.
+fn obstacle_distance(obstacle: Obstacle, position: vec2<f32>) -> vec3<f32> {
+    var offset: vec2<f32>;
+    switch (obstacle.kind) {
+        case OBSTACLE_RECTANGLE: {
+            let p = position - obstacle.a;
+            let q = abs(p) - obstacle.b;
+            let side = select(vec2<f32>(1.), vec2<f32>(-1.), p < vec2<f32>(0.));
+            if (q.x <= 0. && q.y <= 0.) {
+                // inside, the closest edge
+                if (q.x > q.y) {
+                    return vec3<f32>(q.x, side.x, 0.);
+                }
+                return vec3<f32>(q.y, 0., side.y);
+            }
+            let outside = max(q, vec2<f32>(0.));
+            let distance = length(outside);
+            return vec3<f32>(distance, side * outside / distance);
+        }
+        case OBSTACLE_SEGMENT: {
+            let pa = position - obstacle.a;
+            let ba = obstacle.b - obstacle.a;
+            let h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-6), 0., 1.);
+            offset = pa - ba * h;
+        }
+        default: {
+            offset = position - obstacle.a;
+        }
+    }
+    let distance = length(offset);
+    if (distance == 0.) {
+        return vec3<f32>(-obstacle.radius, 0., 1.);
+    }
+    return vec3<f32>(distance - obstacle.radius, offset / distance);
+}
+
+// Repulsion of the obstacles that repel, like the soft walls.
+fn obstacle_acceleration(position: vec2<f32>) -> vec2<f32> {
+    let rmin = max(settings.min_distance, 1.);
+    var a = vec2<f32>(0.);
+    for (var i = 0u; i < settings.obstacle_count; i++) {
+        let obstacle = obstacles[i];
+        if (obstacle.response != OBSTACLE_REPEL) {
+            continue;
+        }
+        let d = obstacle_distance(obstacle, position);
+        a += max(0., 1. - d.x / rmin) * d.yz;
+    }
+    return a;
+}
+
+// The acceleration from the mouse brush, falling off linearly towards its radius.
+fn brush_acceleration(position: vec2<f32>) -> vec2<f32> {
+    if (settings.brush_mode == BRUSH_NONE) {
+        return vec2<f32>(0.);
+    }
+    let dpos = other_position(position, settings.brush_position) - position;
+    let dist = length(dpos);
+    if (dist == 0. || dist > settings.brush_radius) {
+        return vec2<f32>(0.);
+    }
+    let dir = dpos / dist;
+    let force = settings.brush_strength * (1. - dist / settings.brush_radius);
+    switch (settings.brush_mode) {
+        case BRUSH_ATTRACT: { return dir * force; }
+        case BRUSH_REPEL: { return -dir * force; }
+        default: { return vec2<f32>(-dir.y, dir.x) * force; }
+    }
+}
+
+// The acceleration from all force fields.
+fn force_field_acceleration(position: vec2<f32>) -> vec2<f32> {
+    var a = vec2<f32>(0.);
+    for (var i = 0u; i < settings.force_field_count; i++) {
+        let field = force_fields[i];
+        switch (field.kind) {
+            case FIELD_GRAVITY: {
+                a += field.vector;
+            }
+            case FIELD_NOISE: {
+                let p = position / field.radius;
+                a += field.strength * curl_noise(p, settings.simulated_time * field.speed);
+            }
+            case FIELD_ATTRACTOR, FIELD_VORTEX: {
+                // like the brush
+                let dpos = other_position(position, field.vector) - position;
+                let dist = length(dpos);
+                if (dist == 0. || dist > field.radius) {
+                    continue;
+                }
+                let dir = dpos / dist;
+                let force = field.strength * (1. - dist / field.radius);
+                if (field.kind == FIELD_ATTRACTOR) {
+                    a += dir * force;
+                } else {
+                    a += vec2<f32>(-dir.y, dir.x) * force;
+                }
+            }
+            default: {}
+        }
+    }
+    return a;
+}
+
+// Divergence free flow, the curl of value noise over the position at time t.
+fn curl_noise(p: vec2<f32>, t: f32) -> vec2<f32> {
+    let e = 0.01;
+    let dx = value_noise(vec3<f32>(p.x + e, p.y, t)) - value_noise(vec3<f32>(p.x - e, p.y, t));
+    let dy = value_noise(vec3<f32>(p.x, p.y + e, t)) - value_noise(vec3<f32>(p.x, p.y - e, t));
+    return vec2<f32>(dy, -dx) / (2. * e);
+}
+
+// Smoothly interpolated random values at the integer points, in [0, 1].
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3. - 2. * f);
+    let x00 = mix(lattice_value(i), lattice_value(i + vec3<i32>(1, 0, 0)), u.x);
+    let x10 = mix(lattice_value(i + vec3<i32>(0, 1, 0)), lattice_value(i + vec3<i32>(1, 1, 0)), u.x);
+    let x01 = mix(lattice_value(i + vec3<i32>(0, 0, 1)), lattice_value(i + vec3<i32>(1, 0, 1)), u.x);
+    let x11 = mix(lattice_value(i + vec3<i32>(0, 1, 1)), lattice_value(i + vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+fn lattice_value(p: vec3<i32>) -> f32 {
+    let h = pcg_hash(bitcast<u32>(p.x) ^ pcg_hash(bitcast<u32>(p.y) ^ pcg_hash(bitcast<u32>(p.z))));
+    return f32(h) / 4294967295.;
+}
+
+// A random velocity change of the particle with a standard deviation of
+// temperature * sqrt(delta_time) per axis, so the velocities spread the same
+// no matter how long the steps are.
+fn brownian_kick(index: u32) -> vec2<f32> {
+    let a = pcg_hash(settings.seed ^ pcg_hash(index));
+    let b = pcg_hash(a);
+    // Box-Muller transform of two uniform random numbers into two normally distributed ones
+    let radius = sqrt(-2. * log(max(f32(a) / 4294967295., 1e-7)));
+    let angle = 2. * PI * f32(b) / 4294967295.;
+    let normal = radius * vec2<f32>(cos(angle), sin(angle));
+    return normal * settings.temperature * sqrt(settings.delta_time);
+}
+
+// Bit r is set if the catalyst of reaction r is a particle of color other
+// at the squared distance for a particle of color color.
+fn reactions_in_range(color: u32, other: u32, distance_squared: f32) -> u32 {
+    var in_range = 0u;
+    for (var r = 0u; r < settings.reaction_count; r++) {
+        let reaction = reactions[r];
+        if (reaction.color == color && reaction.catalyst == other
+            && distance_squared <= reaction.radius * reaction.radius) {
+            in_range |= 1u << r;
+        }
+    }
+    return in_range;
+}
+
+// The color of the particle after the reactions whose catalysts are in range,
+// the first reaction that happens wins.
+fn react(index: u32, color: u32, in_range: u32) -> u32 {
+    let a = pcg_hash(settings.seed ^ pcg_hash(index));
+    for (var r = 0u; r < settings.reaction_count; r++) {
+        if ((in_range & (1u << r)) == 0u) {
+            continue;
+        }
+        let reaction = reactions[r];
+        let random = f32(pcg_hash(a + r + 1u)) / 4294967295.;
+        if (random < reaction_chance(reaction.probability)) {
+            return reaction.product;
+        }
+    }
+    return color;
+}
+
+// The chance that a reaction with the probability per second happens in a step.
+// Nothing happens while paused, even at a probability of 1.
+fn reaction_chance(probability: f32) -> f32 {
+    if (settings.delta_time <= 0.) {
+        return 0.;
+    }
+    if (probability >= 1.) {
+        return 1.;
+    }
+    return 1. - pow(1. - max(probability, 0.), settings.delta_time);
+}
+
+// https://www.jcgt.org/published/0009/03/02/
+fn pcg_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// How a particle of color x reacts to a particle of color y.
+fn get_interaction(x: u32, y: u32) -> Interaction {
+    return interactions[x + y * settings.max_color_count];
+}
+
 fn get_matrix_value(x: u32, y: u32) -> f32 {
-    // var s = settings;
-    let flat_index = x + y * settings.max_color_count;
-    let index = flat_index / 4;
-    let offset = flat_index % 4;
-    return settings.matrix[index][offset];
+    return get_interaction(x, y).attraction;
+}
+
+// The min and max radius for a particle of color x interacting with a particle of color y.
+fn get_radii(x: u32, y: u32) -> vec2<f32> {
+    if (settings.per_pair_radii == 0u) {
+        return vec2<f32>(settings.min_distance, settings.max_distance);
+    }
+    let interaction = get_interaction(x, y);
+    return vec2<f32>(interaction.min_radius, interaction.max_radius);
 }
 
 fn cell_count() -> u32 {
@@ -50,7 +296,8 @@ fn cell_index(position: vec2<f32>) -> u32 {
 fn cell_index_2d(position: vec2<f32>) -> vec2<u32> {
     // moving the position from [-bounds, bounds] to [0, 2 * bounds];
     let p = settings.bounds + position;
-    return vec2<u32>(floor(p / settings.max_distance));
+    // particles exactly on the upper bounds still belong to the last cell
+    return min(vec2<u32>(floor(p / settings.max_distance)), settings.cell_count - 1u);
 }
 
 fn surrounding_cells(position: vec2<f32>) -> array<u32, 9> {
@@ -63,7 +310,7 @@ fn surrounding_cells(position: vec2<f32>) -> array<u32, 9> {
    let middle_x = cell.x;
    let middle_y = cell.y * cells.x;
 
-   return array(
+   var surrounding = array(
         minus_x + minus_y,
         middle_x + minus_y,
         plus_x + minus_y,
@@ -74,6 +321,21 @@ fn surrounding_cells(position: vec2<f32>) -> array<u32, 9> {
         middle_x + plus_y,
         plus_x + plus_y,
    );
+
+   // without wrapping, cells on the opposite side of the bounds are not neighbours
+   if (settings.boundary_mode != BOUNDARY_WRAP) {
+        let low = cell == vec2<u32>(0u);
+        let high = cell + 1u == cells;
+        for (var i = 0u; i < 9u; i++) {
+            let x = i % 3u;
+            let y = i / 3u;
+            if (x == 0u && low.x) || (x == 2u && high.x) || (y == 0u && low.y) || (y == 2u && high.y) {
+                surrounding[i] = NO_CELL;
+            }
+        }
+   }
+
+   return surrounding;
 }
 
 // This only works for [-1, modulo - 1].
@@ -144,3 +406,67 @@ fn planets(rmin: f32, dpos: vec2<f32>, a: f32) -> vec2<f32> {
     let dist = max(0.01, length(dpos));
     return dpos * 0.01 / (dist * dist * dist);
 }
+
+// Polynomial fits of the matplotlib colormaps by Matt Zucker, https://www.shadertoy.com/view/WlfXRN
+fn colormap(t: f32) -> vec3<f32> {
+    var c: array<vec3<f32>, 7>;
+    switch (settings.colormap) {
+        // Magma
+        case 1u: {
+            c = array<vec3<f32>, 7>(
+                vec3<f32>(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933),
+                vec3<f32>(0.2516605407371642, 0.6775232436837668, 2.494026599312351),
+                vec3<f32>(8.353717279216625, -3.577719514958484, 0.3144679030132573),
+                vec3<f32>(-27.66873308576866, 14.26473078096533, -13.64921318813922),
+                vec3<f32>(52.17613981234068, -27.94360607168351, 12.94416944238394),
+                vec3<f32>(-50.76852536473588, 29.04658282127291, 4.23415299384598),
+                vec3<f32>(18.65570506591883, -11.48977351997711, -5.601961508734096),
+            );
+        }
+        // Inferno
+        case 2u: {
+            c = array<vec3<f32>, 7>(
+                vec3<f32>(0.0002189403691192265, 0.001651004631001012, -0.01948089843709184),
+                vec3<f32>(0.1065134194856116, 0.5639564367884091, 3.932712388889277),
+                vec3<f32>(11.60249308247187, -3.972853965665698, -15.9423941062914),
+                vec3<f32>(-41.70399613139459, 17.43639888205313, 44.35414519872813),
+                vec3<f32>(77.162935699427, -33.40235894210092, -81.80730925738993),
+                vec3<f32>(-71.31942824499214, 32.62606426397723, 73.20951985803202),
+                vec3<f32>(25.13112622477341, -12.24266895238567, -23.07032500287172),
+            );
+        }
+        // Plasma
+        case 3u: {
+            c = array<vec3<f32>, 7>(
+                vec3<f32>(0.05873234392399702, 0.02333670892565664, 0.5433401826748754),
+                vec3<f32>(2.176514634195958, 0.2383834171260182, 0.7539604599784036),
+                vec3<f32>(-2.689460476458034, -7.455851135738909, 3.110799939717086),
+                vec3<f32>(6.130348345893603, 42.3461881477227, -28.51885465332158),
+                vec3<f32>(-11.10743619062271, -82.66631109428045, 60.13984767418263),
+                vec3<f32>(10.02306557647065, 71.41361770095349, -54.07218655560067),
+                vec3<f32>(-3.658713842777788, -22.93153465461149, 18.19190778539828),
+            );
+        }
+        // Viridis
+        default: {
+            c = array<vec3<f32>, 7>(
+                vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061),
+                vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685),
+                vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659),
+                vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987),
+                vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105),
+                vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234),
+                vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832),
+            );
+        }
+    }
+    let color = c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * (c[5] + t * c[6])))));
+    return clamp(color, vec3<f32>(0.), vec3<f32>(1.));
+}
+
+// The colormaps are in srgb, but the shader outputs linear colors
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
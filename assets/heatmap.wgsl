@@ -0,0 +1,20 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import types::{settings, view, counter};
+#import functions::{cell_index, colormap, srgb_to_linear};
+
+// The particle count of the grid cell under the pixel. After sort_particles,
+// counter holds the start of each cell, so the count is the distance to the next start.
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let clip_position = vec2<f32>(in.uv.x * 2. - 1., 1. - in.uv.y * 2.);
+    let world_position = view.world_from_clip * vec4<f32>(clip_position, 0., 1.);
+    let position = world_position.xy / world_position.w;
+    if (any(abs(position) > settings.bounds)) {
+        return vec4<f32>(0.);
+    }
+
+    let cell = cell_index(position);
+    let count = atomicLoad(&counter[cell + 1u]) - atomicLoad(&counter[cell]);
+    let value = clamp(f32(count) / settings.heatmap_max, 0., 1.);
+    return vec4<f32>(srgb_to_linear(colormap(value)), settings.heatmap_opacity);
+}
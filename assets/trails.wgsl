@@ -0,0 +1,17 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0) var trail_texture: texture_2d<f32>;
+@group(0) @binding(1) var trail_sampler: sampler;
+
+// The blend state multiplies the trails with the decay (the blend constant),
+// so the output itself doesn't matter.
+@fragment
+fn fade() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.);
+}
+
+// The trails are premultiplied, faded particles are transparent as well as darker.
+@fragment
+fn composite(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(trail_texture, trail_sampler, in.uv);
+}
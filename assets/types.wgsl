@@ -12,6 +12,28 @@
 var<storage, read_write> prefix_sum_reduction: array<atomic<u32>>;
 @group(0) @binding(6)
 var<storage, read_write> prefix_sum_index: array<atomic<u32>>;
+// the linear colors of the particle colors
+@group(0) @binding(7) var<storage, read> colors: array<vec4<f32>>;
+// max_color_count x max_color_count matrix, see get_interaction
+@group(0) @binding(8) var<storage, read> interactions: array<Interaction>;
+// copy of the particles that erase_particles compacts into particles
+@group(0) @binding(9) var<storage, read> particles_copy: array<Particle>;
+// amount of particles left after erase_particles, u32::MAX while no readback is in flight
+@group(0) @binding(10) var<storage, read_write> erase_count: atomic<u32>;
+// neighbour count and force magnitude of each particle, written by update_velocity
+@group(0) @binding(11) var<storage, read_write> particle_fields: array<vec2<f32>>;
+// the first settings.obstacle_count are the obstacles, see Obstacle
+@group(0) @binding(12) var<storage, read> obstacles: array<Obstacle>;
+// the first settings.force_field_count are the force fields, see ForceField
+@group(0) @binding(13) var<storage, read> force_fields: array<ForceField>;
+// the parameters of each color, same layout as colors
+@group(0) @binding(14) var<storage, read> species: array<Species>;
+// the first settings.reaction_count are the reactions, see Reaction
+@group(0) @binding(15) var<storage, read> reactions: array<Reaction>;
+// the color of each particle after the reactions of this step, see update_velocity
+@group(0) @binding(16) var<storage, read_write> next_colors: array<u32>;
+// the particle closest to probe.cursor for the grid debug overlay, see probe_particle
+@group(0) @binding(17) var<storage, read_write> probe: Probe;
 
 struct Particles {
     particles: array<Particle>,
@@ -27,15 +49,22 @@ struct Particle {
 struct Settings {
     time: f32,
     delta_time: f32,
+    simulated_time: f32,
     particle_count: u32,
     min_distance: f32,
     max_distance: f32,
     max_velocity: f32,
     velocity_half_life: f32,
     force_factor: f32,
+    temperature: f32,
     bounds: vec2<f32>,
     max_attractions: u32,
     acceleration_method: u32,
+    boundary_mode: u32,
+    per_pair_radii: u32,
+    obstacle_count: u32,
+    force_field_count: u32,
+    reaction_count: u32,
 
     new_particles: u32,
     initialized_particles: u32,
@@ -45,12 +74,80 @@ struct Settings {
     particle_size: f32,
     rgb: u32,
     rgb_speed: f32,
+    color_mode: u32,
+    colormap: u32,
+    color_max: f32,
+    heatmap_opacity: f32,
+    heatmap_max: f32,
+
+    brush_mode: u32,
+    brush_position: vec2<f32>,
+    brush_radius: f32,
+    brush_strength: f32,
+
+    erase_position: vec2<f32>,
+    erase_radius: f32,
 
     cell_count: vec2<u32>,
     seed: u32,
 
     color_count: u32,
     max_color_count: u32,
-    colors: array<vec4<f32>, 16>,
-    matrix: array<vec4<f32>, 5>,
+}
+
+// Polylines are split into segments.
+// Circles: a = center, radius. Rectangles: a = center, b = half size. Segments: a to b, radius.
+struct Obstacle {
+    kind: u32,
+    response: u32,
+    a: vec2<f32>,
+    b: vec2<f32>,
+    radius: f32,
+    padding: u32,
+}
+
+// Gravity: vector = acceleration. Attractors and vortices: vector = center, strength, radius.
+// Noise: strength, radius = scale, speed.
+struct ForceField {
+    kind: u32,
+    strength: f32,
+    vector: vec2<f32>,
+    radius: f32,
+    speed: f32,
+}
+
+// size is a factor of settings.particle_size
+struct Species {
+    mass: f32,
+    velocity_half_life: f32,
+    max_velocity: f32,
+    size: f32,
+}
+
+// A particle of color within radius of a particle of color catalyst turns into
+// color product with probability per second.
+struct Reaction {
+    color: u32,
+    catalyst: u32,
+    product: u32,
+    radius: f32,
+    probability: f32,
+}
+
+// cell_index is NO_CELL if there is no particle within max_distance of the cursor.
+// start and end are the range of sorted_indices with the particles of the cell.
+struct Probe {
+    cursor: vec2<f32>,
+    position: vec2<f32>,
+    index: u32,
+    cell_index: u32,
+    start: u32,
+    end: u32,
+}
+
+struct Interaction {
+    attraction: f32,
+    min_radius: f32,
+    max_radius: f32,
+    padding: u32,
 }
@@ -0,0 +1,163 @@
+//! Command line arguments of the windowed app.
+//!
+//! Usage: `bevy_particle_life [options]`, `bevy_particle_life headless ...` for batch mode,
+//! see [`crate::headless`], or `bevy_particle_life render ...` for posters, see [`crate::poster`].
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::data::SimulationSettings;
+
+pub const USAGE: &str = "\
+Usage: bevy_particle_life [options]
+       bevy_particle_life headless (--settings <file> | --snapshot <file>) --steps <n> --output <file> [--delta-time <seconds>]
+       bevy_particle_life render (--settings <file> | --snapshot <file>) --size <WxH> --output <file> [--steps <n>] [--delta-time <seconds>] [--particle-size <size>]
+
+Options:
+  --settings <file>      load settings (as copied with \"Copy settings to clipboard\") at startup
+  --seed <n>             seed for all random decisions
+  --particles <n>        particle count, overrides the settings
+  --window-size <WxH>    window size in logical pixels, e.g. 1920x1080
+  --fullscreen           start in borderless fullscreen
+  --zoom <factor>        initial camera zoom, 2 shows half as much of the world
+  --camera <x,y>         initial camera position in world coordinates
+  --hide-ui              start without the settings window, F1 shows it
+  --help                 print this message";
+
+#[derive(Resource, Debug, Clone, PartialEq, Default)]
+pub struct Args {
+    pub settings: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub particles: Option<usize>,
+    pub window_size: Option<Vec2>,
+    pub fullscreen: bool,
+    pub zoom: Option<f32>,
+    pub camera: Option<Vec2>,
+    pub hide_ui: bool,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--settings" => parsed.settings = Some(PathBuf::from(value()?)),
+                "--seed" => {
+                    parsed.seed = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --seed: {e}"))?,
+                    )
+                }
+                "--particles" => {
+                    parsed.particles = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --particles: {e}"))?,
+                    )
+                }
+                "--window-size" => {
+                    parsed.window_size = Some(
+                        parse_pair(value()?, 'x')
+                            .filter(|size| size.min_element() > 0.)
+                            .ok_or("invalid --window-size, expected e.g. 1920x1080")?,
+                    )
+                }
+                "--fullscreen" => parsed.fullscreen = true,
+                "--zoom" => {
+                    parsed.zoom = Some(
+                        value()?
+                            .parse()
+                            .ok()
+                            .filter(|zoom: &f32| *zoom > 0.)
+                            .ok_or("invalid --zoom, expected a positive number")?,
+                    )
+                }
+                "--camera" => {
+                    parsed.camera = Some(
+                        parse_pair(value()?, ',')
+                            .ok_or("invalid --camera, expected e.g. 100,-250")?,
+                    )
+                }
+                "--hide-ui" => parsed.hide_ui = true,
+                "--help" | "-h" => parsed.help = true,
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// The settings to start with: the settings file or the default settings
+    /// with random attractions, with the overrides applied.
+    pub fn initial_settings(&self) -> Result<SimulationSettings, String> {
+        let mut settings = match &self.settings {
+            Some(path) => {
+                let settings = std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                SimulationSettings::deserialize(&settings)
+                    .map_err(|e| format!("invalid settings in {}: {e}", path.display()))?
+            }
+            None => {
+                let mut settings = SimulationSettings::default();
+                settings.seed = self.seed;
+                settings.randomize_attractions();
+                settings
+            }
+        };
+
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+        if let Some(particles) = self.particles {
+            settings.particle_count = particles;
+        }
+        Ok(settings)
+    }
+}
+
+fn parse_pair(s: &str, separator: char) -> Option<Vec2> {
+    let (x, y) = s.split_once(separator)?;
+    Some(Vec2::new(x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+#[test]
+fn test_parse_args() {
+    let args: Vec<String> = [
+        "--seed",
+        "5",
+        "--window-size",
+        "1280x720",
+        "--camera",
+        "10,-20.5",
+        "--zoom",
+        "2",
+        "--hide-ui",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let parsed = Args::parse(&args).unwrap();
+    assert_eq!(parsed.seed, Some(5));
+    assert_eq!(parsed.window_size, Some(Vec2::new(1280., 720.)));
+    assert_eq!(parsed.camera, Some(Vec2::new(10., -20.5)));
+    assert_eq!(parsed.zoom, Some(2.));
+    assert!(parsed.hide_ui);
+    assert!(!parsed.fullscreen);
+
+    let settings = parsed.initial_settings().unwrap();
+    assert_eq!(settings.seed, Some(5));
+    assert_eq!(settings.matrix, parsed.initial_settings().unwrap().matrix);
+
+    assert!(Args::parse(&["--window-size".to_string(), "big".to_string()]).is_err());
+    assert!(Args::parse(&["--zoom".to_string(), "0".to_string()]).is_err());
+    assert!(Args::parse(&["--unknown".to_string()]).is_err());
+}
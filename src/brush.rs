@@ -0,0 +1,163 @@
+use std::f32::consts::TAU;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use rand::Rng;
+
+use crate::{
+    camera::ParticleCamera,
+    compute::ErasedParticles,
+    data::{ColorId, Particle, SimulationSettings},
+    events::ParticleEvent,
+};
+
+/// What the brush does to the particles inside of its radius.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushMode {
+    #[default]
+    Attract = 1,
+    Repel = 2,
+    Swirl = 3,
+    /// Spawns particles of the brush color
+    Paint,
+    /// Removes particles
+    Erase,
+}
+
+/// A radial force field at the cursor, applied while holding ctrl and the left mouse button.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Brush {
+    pub mode: BrushMode,
+    pub radius: f32,
+    pub strength: f32,
+    /// The color painted particles get
+    pub color: u32,
+    /// Particles spawned per second while painting
+    pub paint_rate: f32,
+    /// The world space position of the cursor while the brush is applied
+    pub position: Option<Vec2>,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::Attract,
+            radius: 300.,
+            strength: 3000.,
+            color: 0,
+            paint_rate: 2000.,
+            position: None,
+        }
+    }
+}
+
+impl Brush {
+    /// The mode as used in the shaders, 0 if the brush isn't applied.
+    pub fn gpu_mode(&self) -> u32 {
+        match (self.position, self.mode) {
+            (None, _) | (_, BrushMode::Paint | BrushMode::Erase) => 0,
+            (Some(_), mode) => mode as u32,
+        }
+    }
+}
+
+pub fn brush_held(keyboard: &ButtonInput<KeyCode>) -> bool {
+    keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_brush(
+    mut brush: ResMut<Brush>,
+    camera: Query<(&Camera, &GlobalTransform), With<ParticleCamera>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut egui_contexts: EguiContexts,
+    mut settings: ResMut<SimulationSettings>,
+    mut event_writer: EventWriter<ParticleEvent>,
+    erased: Res<ErasedParticles>,
+    time: Res<Time<Real>>,
+    // fraction of a particle that is carried over to the next frame while painting
+    mut paint_remainder: Local<f32>,
+) {
+    let egui_context = egui_contexts.ctx_mut();
+    let block_mouse = egui_context.is_pointer_over_area() || egui_context.is_using_pointer();
+
+    brush.position = None;
+    if block_mouse || !brush_held(&keyboard) || !mouse.pressed(MouseButton::Left) {
+        return;
+    }
+
+    let (camera, camera_transform) = camera.single();
+    brush.position = window
+        .single()
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor));
+    let Some(position) = brush.position else {
+        return;
+    };
+
+    match brush.mode {
+        // waiting until the particles left after erasing are known
+        BrushMode::Paint if erased.reading() => {}
+        BrushMode::Paint => {
+            *paint_remainder += brush.paint_rate * time.delta_seconds();
+            let count = *paint_remainder as u32;
+            *paint_remainder -= count as f32;
+            if count > 0 {
+                settings.particle_count += count as usize;
+                event_writer.send(ParticleEvent::Spawn {
+                    position,
+                    radius: brush.radius,
+                    color: brush.color.min(settings.color_count as u32 - 1),
+                    count,
+                });
+            }
+        }
+        BrushMode::Erase => {
+            event_writer.send(ParticleEvent::Erase {
+                position,
+                radius: brush.radius,
+            });
+        }
+        _ => {}
+    }
+}
+
+/// `count` resting particles uniformly distributed in the circle, clamped to the bounds.
+pub fn particles_in_circle(
+    position: Vec2,
+    radius: f32,
+    color: u32,
+    count: u32,
+    bounds: Vec2,
+    rng: &mut impl Rng,
+) -> Vec<Particle> {
+    (0..count)
+        .map(|_| {
+            let distance = radius * rng.gen::<f32>().sqrt();
+            let offset = Vec2::from_angle(rng.gen::<f32>() * TAU) * distance;
+            Particle {
+                position: (position + offset).clamp(-bounds, bounds),
+                velocity: Vec2::ZERO,
+                color: ColorId::new(color),
+                padding: 0,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_particles_in_circle() {
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let bounds = Vec2::new(100., 100.);
+    let particles = particles_in_circle(Vec2::new(90., 0.), 20., 2, 500, bounds, &mut rng);
+    assert_eq!(particles.len(), 500);
+    for p in &particles {
+        assert!(p.position.distance(Vec2::new(90., 0.)) <= 20.001);
+        assert!(p.position.x <= bounds.x);
+        assert_eq!(p.color, ColorId::new(2));
+    }
+}
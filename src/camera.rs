@@ -5,9 +5,16 @@ use bevy::{
 };
 use bevy_egui::EguiContexts;
 
+use crate::brush::brush_held;
+
 #[derive(Component, ExtractComponent, Debug, Clone, Copy, Default)]
 pub struct ParticleCamera;
 
+/// Draws the particles of this camera with a different size than settings.particle_size,
+/// e.g. for a poster.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ParticleSize(pub f32);
+
 #[derive(Component, Debug, Clone, Copy)]
 pub struct CameraSettings {
     pub pan_speed: f32,
@@ -28,7 +35,12 @@ pub fn camera_controls(
     let (mut camera, mut projection, settings) = camera.single_mut();
 
     let mut translation_from_mouse = Vec3::ZERO;
-    if mouse.pressed(MouseButton::Left) | mouse.pressed(MouseButton::Right) {
+    // dragging with the left mouse button applies the brush while it is held
+    let pan_button = match brush_held(&keyboard) {
+        true => mouse.pressed(MouseButton::Right),
+        false => mouse.pressed(MouseButton::Left) | mouse.pressed(MouseButton::Right),
+    };
+    if pan_button {
         for event in mouse_motion.read() {
             translation_from_mouse += Vec3::new(-event.delta.x, event.delta.y, 0.);
         }
@@ -0,0 +1,153 @@
+//! Screenshots and png frame sequences of the window.
+//!
+//! F12 saves a screenshot, F10 starts and stops a recording. While recording, the simulation
+//! runs with a fixed time step and every nth frame is saved as a numbered png, so the frames
+//! can be assembled into a video offline, independent of the real frame rate.
+//! The settings window is hidden in captured frames.
+
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow};
+
+use crate::compute::DeltaTimeOverride;
+
+pub const SCREENSHOT_KEY: KeyCode = KeyCode::F12;
+pub const RECORD_KEY: KeyCode = KeyCode::F10;
+
+#[derive(Resource, Debug, Clone)]
+pub struct Capture {
+    /// Screenshots and recordings are saved in here
+    pub directory: PathBuf,
+    /// While recording, only every nth frame is saved
+    pub every_nth_frame: u32,
+    /// The fixed simulation time step while recording
+    pub delta_time: f32,
+    /// Take a screenshot in the next frame
+    pub screenshot_requested: bool,
+    /// A screenshot of the current frame is being taken
+    taking_screenshot: bool,
+    recording: Option<Recording>,
+}
+
+#[derive(Debug, Clone)]
+struct Recording {
+    directory: PathBuf,
+    frame: u32,
+    saved_frames: u32,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("captures"),
+            every_nth_frame: 1,
+            delta_time: 1. / 60.,
+            screenshot_requested: false,
+            taking_screenshot: false,
+            recording: None,
+        }
+    }
+}
+
+impl Capture {
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Whether the ui should be hidden, so it doesn't show up in the captured frame.
+    pub fn hides_ui(&self) -> bool {
+        self.taking_screenshot || self.is_recording()
+    }
+
+    pub fn start_recording(&mut self) {
+        if self.is_recording() {
+            return;
+        }
+        let directory = self.directory.join(format!("recording-{}", timestamp()));
+        info!("Recording to {}", directory.display());
+        self.recording = Some(Recording {
+            directory,
+            frame: 0,
+            saved_frames: 0,
+        });
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            info!(
+                "Saved {} frames to {}",
+                recording.saved_frames,
+                recording.directory.display()
+            );
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch, used to get unique file names.
+pub fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+pub fn capture(
+    mut capture: ResMut<Capture>,
+    mut delta_time_override: ResMut<DeltaTimeOverride>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    window: Query<Entity, With<PrimaryWindow>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+) {
+    let window = window.single();
+
+    if keyboard.just_pressed(RECORD_KEY) {
+        match capture.is_recording() {
+            true => capture.stop_recording(),
+            false => capture.start_recording(),
+        }
+    }
+    // the settings keep their own time step, so it is back once the recording stops
+    delta_time_override.0 = capture.is_recording().then_some(capture.delta_time);
+    if keyboard.just_pressed(SCREENSHOT_KEY) {
+        capture.screenshot_requested = true;
+    }
+
+    capture.taking_screenshot = false;
+    if std::mem::take(&mut capture.screenshot_requested) {
+        let path = capture
+            .directory
+            .join(format!("screenshot-{}.png", timestamp()));
+        capture.taking_screenshot = save_frame(&mut screenshot_manager, window, path);
+    }
+
+    let every_nth_frame = capture.every_nth_frame.max(1);
+    let Some(recording) = &mut capture.recording else {
+        return;
+    };
+    recording.frame += 1;
+    if recording.frame % every_nth_frame == 0 {
+        let path = recording
+            .directory
+            .join(format!("frame-{:05}.png", recording.saved_frames));
+        if save_frame(&mut screenshot_manager, window, path) {
+            recording.saved_frames += 1;
+        }
+    }
+}
+
+/// Saves the window as png once the current frame is rendered.
+fn save_frame(screenshot_manager: &mut ScreenshotManager, window: Entity, path: PathBuf) -> bool {
+    if let Some(directory) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(directory) {
+            error!("Failed to create {}: {e}", directory.display());
+            return false;
+        }
+    }
+    // fails if a screenshot of this frame is already being taken
+    screenshot_manager
+        .save_screenshot_to_disk(window, path)
+        .is_ok()
+}
@@ -1,4 +1,7 @@
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
 
 use bevy::{
     asset::load_internal_asset,
@@ -8,22 +11,27 @@ use bevy::{
         graph::CameraDriverLabel,
         render_graph::{self, RenderGraph, RenderLabel},
         render_resource::{
-            binding_types::{storage_buffer, uniform_buffer},
-            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BufferUsages,
-            CachedComputePipelineId, CommandEncoderDescriptor, ComputePassDescriptor, Maintain,
-            PipelineCache, ShaderStages, ShaderType, StorageBuffer, UniformBuffer, UninitBufferVec,
+            binding_types::{storage_buffer, storage_buffer_read_only, uniform_buffer},
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePassDescriptor,
+            Maintain, MapMode, PipelineCache, ShaderStages, ShaderType, StorageBuffer,
+            UniformBuffer, UninitBufferVec,
         },
         renderer::{RenderDevice, RenderQueue},
         view::{ViewUniform, ViewUniforms},
         Extract, Render, RenderApp, RenderSet,
     },
 };
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::{
+    brush::{particles_in_circle, Brush},
     camera::ParticleCamera,
-    data::{Particle, SimulationSettings, COLORS},
+    data::{palette_color, ForceField, ObstacleShape, Particle, SimulationSettings, MAX_REACTIONS},
     events::ParticleEvent,
+    grid_debug::ProbeReadback,
+    heatmap::Heatmap,
+    snapshot::{particles_to_bytes, SnapshotQueue},
 };
 
 pub const SHADER_FUNCTIONS: Handle<Shader> = Handle::weak_from_u128(4912569123382610166);
@@ -31,6 +39,8 @@ pub const SHADER_TYPES: Handle<Shader> = Handle::weak_from_u128(4923569123382510
 pub const SHADER_COMPUTE: Handle<Shader> = Handle::weak_from_u128(4313569123342610166);
 pub const SHADER_DRAW: Handle<Shader> = Handle::weak_from_u128(3913559123382610166);
 pub const SHADER_PREFIX_SUM: Handle<Shader> = Handle::weak_from_u128(3913559123182610166);
+pub const SHADER_TRAILS: Handle<Shader> = Handle::weak_from_u128(3913559125182610166);
+pub const SHADER_HEATMAP: Handle<Shader> = Handle::weak_from_u128(3913559125182710166);
 
 fn load_shaders(app: &mut App) {
     load_internal_asset!(app, SHADER_TYPES, "../assets/types.wgsl", Shader::from_wgsl);
@@ -55,6 +65,20 @@ fn load_shaders(app: &mut App) {
         "../assets/prefix_sum.wgsl",
         Shader::from_wgsl
     );
+
+    load_internal_asset!(
+        app,
+        SHADER_TRAILS,
+        "../assets/trails.wgsl",
+        Shader::from_wgsl
+    );
+
+    load_internal_asset!(
+        app,
+        SHADER_HEATMAP,
+        "../assets/heatmap.wgsl",
+        Shader::from_wgsl
+    );
 }
 
 const WORKGROUP_SIZE: u32 = 64;
@@ -67,12 +91,21 @@ impl Plugin for ComputePlugin {
 
         app.add_plugins(ExtractComponentPlugin::<ParticleCamera>::default());
 
+        let erased = ErasedParticles::default();
+        app.init_resource::<DeltaTimeOverride>()
+            .insert_resource(erased.clone())
+            .add_systems(First, apply_erased_particles);
+
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .insert_resource(erased)
             .add_systems(ExtractSchedule, extract_particle_related_things)
             .add_systems(
                 Render,
-                prepare_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                (
+                    prepare_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                    read_erased_particles.in_set(RenderSet::Cleanup),
+                ),
             );
 
         let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
@@ -84,6 +117,7 @@ impl Plugin for ComputePlugin {
         let render_app = app.sub_app_mut(RenderApp);
         render_app.insert_resource(GpuBuffers::new());
         render_app.init_resource::<Todo>();
+        render_app.init_resource::<EraseReadback>();
         render_app.init_resource::<ParticleBindGroupLayouts>();
         render_app.init_resource::<ParticlePipelines>();
     }
@@ -93,66 +127,290 @@ impl Plugin for ComputePlugin {
 pub struct GpuSettings {
     pub time: f32,
     pub delta_time: f32,
+    /// Sum of the delta times, unlike time it follows fixed delta times
+    pub simulated_time: f32,
     pub particle_count: u32,
     pub min_distance: f32,
     pub max_distance: f32,
     pub max_velocity: f32,
     pub velocity_half_life: f32,
     pub force_factor: f32,
+    pub temperature: f32,
     pub bounds: Vec2,
     pub max_attractions: u32,
     pub acceleration_method: u32,
+    // 0 = Wrap, 1 = Reflect, 2 = Clamp, 3 = SoftWall
+    pub boundary_mode: u32,
+    pub per_pair_radii: u32,
+    pub obstacle_count: u32,
+    pub force_field_count: u32,
+    pub reaction_count: u32,
 
     pub new_particles: u32,
     pub initialized_particles: u32,
 
-    // 0 = Circle, 1 = Square
+    // 0 = Circle, 1 = Square, 2 = Glow
     pub shape: u32,
     pub circle_corners: u32,
     pub particle_size: f32,
     pub rgb: u32,
     pub rgb_speed: f32,
+    // see ColorMode and Colormap
+    pub color_mode: u32,
+    pub colormap: u32,
+    pub color_max: f32,
+    // the density heatmap is hidden if the opacity is 0
+    pub heatmap_opacity: f32,
+    pub heatmap_max: f32,
+
+    // 0 = None, 1 = Attract, 2 = Repel, 3 = Swirl
+    pub brush_mode: u32,
+    pub brush_position: Vec2,
+    pub brush_radius: f32,
+    pub brush_strength: f32,
+
+    // particles inside of this circle are erased at the end of the frame if erase_radius > 0
+    pub erase_position: Vec2,
+    pub erase_radius: f32,
 
     // not setting, but info that the shaders need
     pub cell_count: UVec2,
     pub seed: u32,
 
     pub color_count: u32,
+    // The length of the colors buffer and the row length of the interactions buffer
     pub max_color_count: u32,
-    pub colors: [Vec4; COLORS.len()],
-    // This is actually a COLORS.len()xCOLORS.len() matrix of f32
-    pub matrix: ColorMatrix,
 }
 
-#[derive(ShaderType, Clone, Copy)]
-pub struct ColorMatrix {
-    pub matrix: [Vec4; COLORS.len() * COLORS.len() / 4 + 1],
+/// The seed of the random decisions in a simulation step, derived from settings.seed
+/// so seeded runs are reproducible.
+pub fn step_seed(settings: &SimulationSettings, step: u32) -> u32 {
+    match settings.seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(step as u64)).gen(),
+        None => rand::thread_rng().gen(),
+    }
+}
+
+/// How a particle of color x reacts to a particle of color y.
+/// Stored in a max_color_count x max_color_count matrix at x + y * max_color_count.
+#[derive(ShaderType, Default, Debug, Clone, Copy)]
+pub struct Interaction {
+    pub attraction: f32,
+    pub min_radius: f32,
+    pub max_radius: f32,
+    pub padding: u32,
 }
 
-impl Default for ColorMatrix {
-    fn default() -> Self {
-        Self {
-            matrix: [Vec4::ZERO; COLORS.len() * COLORS.len() / 4 + 1],
+impl Interaction {
+    pub fn matrix(settings: &SimulationSettings) -> Vec<Self> {
+        let size = settings.palette_size();
+        let mut interactions = vec![Self::default(); size * size];
+        for y in 0..size {
+            for x in 0..size {
+                interactions[x + y * size] = Self {
+                    attraction: settings.matrix[y][x],
+                    min_radius: settings.min_radius[y][x],
+                    max_radius: settings.max_radius[y][x],
+                    padding: 0,
+                };
+            }
         }
+        interactions
     }
 }
 
-impl ColorMatrix {
-    pub fn new(array: &[Vec<f32>]) -> Self {
-        let mut this = Self::default();
-        for (y, row) in array.iter().enumerate() {
-            for (x, value) in row.iter().enumerate() {
-                this.set(x, y, *value);
+/// The parameters of a species as the shaders see it, see [`SimulationSettings::species`].
+/// Indexed like the colors buffer.
+#[derive(ShaderType, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GpuSpecies {
+    pub mass: f32,
+    pub velocity_half_life: f32,
+    pub max_velocity: f32,
+    pub size: f32,
+}
+
+impl GpuSpecies {
+    pub fn list(settings: &SimulationSettings) -> Vec<Self> {
+        (0..settings.palette_size())
+            .map(|color| {
+                let species = settings.species(color);
+                Self {
+                    mass: species.mass,
+                    velocity_half_life: species.velocity_half_life,
+                    max_velocity: species.max_velocity,
+                    size: species.size,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A reaction as the shaders see it, see [`crate::data::Reaction`].
+#[derive(ShaderType, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GpuReaction {
+    pub color: u32,
+    pub catalyst: u32,
+    pub product: u32,
+    pub radius: f32,
+    pub probability: f32,
+}
+
+impl GpuReaction {
+    /// Leaves out reactions with colors that aren't in the palette.
+    pub fn list(settings: &SimulationSettings) -> Vec<Self> {
+        let size = settings.palette_size() as u32;
+        settings
+            .reactions
+            .iter()
+            .filter(|r| r.color < size && r.catalyst < size && r.product < size)
+            .take(MAX_REACTIONS)
+            .map(|r| Self {
+                color: r.color,
+                catalyst: r.catalyst,
+                product: r.product,
+                radius: r.radius.min(settings.max_distance() as f32),
+                probability: r.probability,
+            })
+            .collect()
+    }
+}
+
+/// The input and result of probe_particle as the shaders see it, see [`ProbeReadback`].
+#[derive(ShaderType, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GpuProbe {
+    pub cursor: Vec2,
+    pub position: Vec2,
+    pub index: u32,
+    /// u32::MAX if there is no particle within max_distance of the cursor
+    pub cell_index: u32,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// An obstacle as the shaders see it, polylines are split into one capsule per segment.
+#[derive(ShaderType, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GpuObstacle {
+    /// GpuObstacle::CIRCLE, RECTANGLE or SEGMENT
+    pub kind: u32,
+    // see ObstacleResponse
+    pub response: u32,
+    /// Center of circles and rectangles, start of segments
+    pub a: Vec2,
+    /// Half the size of rectangles, end of segments
+    pub b: Vec2,
+    /// Radius of circles and half the thickness of segments
+    pub radius: f32,
+    pub padding: u32,
+}
+
+impl GpuObstacle {
+    // same as in functions.wgsl
+    pub const CIRCLE: u32 = 0;
+    pub const RECTANGLE: u32 = 1;
+    pub const SEGMENT: u32 = 2;
+
+    pub fn list(settings: &SimulationSettings) -> Vec<Self> {
+        let mut obstacles = Vec::new();
+        for obstacle in &settings.obstacles {
+            let response = obstacle.response as u32;
+            match &obstacle.shape {
+                ObstacleShape::Circle { center, radius } => obstacles.push(Self {
+                    kind: Self::CIRCLE,
+                    response,
+                    a: *center,
+                    radius: *radius,
+                    ..default()
+                }),
+                ObstacleShape::Rectangle { center, size } => obstacles.push(Self {
+                    kind: Self::RECTANGLE,
+                    response,
+                    a: *center,
+                    b: *size / 2.,
+                    ..default()
+                }),
+                ObstacleShape::Polyline { points, thickness } => {
+                    obstacles.extend(points.windows(2).map(|segment| Self {
+                        kind: Self::SEGMENT,
+                        response,
+                        a: segment[0],
+                        b: segment[1],
+                        radius: thickness / 2.,
+                        padding: 0,
+                    }))
+                }
             }
         }
-        this
+        obstacles
     }
+}
+
+/// A force field as the shaders see it.
+#[derive(ShaderType, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GpuForceField {
+    /// GpuForceField::GRAVITY, ATTRACTOR, VORTEX or NOISE
+    pub kind: u32,
+    pub strength: f32,
+    /// The acceleration of gravity, the center of attractors and vortices
+    pub vector: Vec2,
+    /// Radius of attractors and vortices, size of the noise swirls
+    pub radius: f32,
+    /// How fast the noise changes
+    pub speed: f32,
+}
 
-    pub fn set(&mut self, x: usize, y: usize, value: f32) {
-        let flat_index = x + y * COLORS.len();
-        let index = flat_index / 4;
-        let offset = flat_index % 4;
-        self.matrix[index][offset] = value.into();
+impl GpuForceField {
+    // same as in functions.wgsl
+    pub const GRAVITY: u32 = 0;
+    pub const ATTRACTOR: u32 = 1;
+    pub const VORTEX: u32 = 2;
+    pub const NOISE: u32 = 3;
+
+    pub fn list(settings: &SimulationSettings) -> Vec<Self> {
+        settings
+            .force_fields
+            .iter()
+            .map(|field| match *field {
+                ForceField::Gravity { acceleration } => Self {
+                    kind: Self::GRAVITY,
+                    strength: 1.,
+                    vector: acceleration,
+                    ..default()
+                },
+                ForceField::Attractor {
+                    center,
+                    strength,
+                    radius,
+                } => Self {
+                    kind: Self::ATTRACTOR,
+                    strength,
+                    vector: center,
+                    radius,
+                    speed: 0.,
+                },
+                ForceField::Vortex {
+                    center,
+                    strength,
+                    radius,
+                } => Self {
+                    kind: Self::VORTEX,
+                    strength,
+                    vector: center,
+                    radius,
+                    speed: 0.,
+                },
+                ForceField::Noise {
+                    strength,
+                    scale,
+                    speed,
+                } => Self {
+                    kind: Self::NOISE,
+                    strength,
+                    radius: scale,
+                    speed,
+                    ..default()
+                },
+            })
+            .collect()
     }
 }
 
@@ -160,6 +418,7 @@ impl ColorMatrix {
 struct Todo {
     randomize_positions: AtomicBool,
     randomize_colors: AtomicBool,
+    read_erased: AtomicBool,
 }
 
 impl Todo {
@@ -178,18 +437,114 @@ impl Todo {
     fn randomize_colors(&self) -> bool {
         self.randomize_colors.load(Ordering::Relaxed)
     }
+
+    fn set_read_erased(&self, value: bool) {
+        self.read_erased.store(value, Ordering::Relaxed);
+    }
+
+    fn read_erased(&self) -> bool {
+        self.read_erased.load(Ordering::Relaxed)
+    }
+}
+
+/// Simulated seconds per frame that take precedence over settings.fixed_delta_time,
+/// without changing the settings, e.g. while recording.
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct DeltaTimeOverride(pub Option<f32>);
+
+/// Keeps settings.particle_count of the main world in sync after particles were erased.
+/// How many particles are left is only known after reading it back from the gpu,
+/// so the main world applies it a few frames later and the render world
+/// subtracts the not yet applied particles in the meantime.
+/// The same resource is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+pub struct ErasedParticles {
+    /// Total amount of particles erased in the render world
+    erased: Arc<AtomicUsize>,
+    /// Total amount of erased particles subtracted from settings.particle_count in the main world
+    applied: Arc<AtomicUsize>,
+    /// Set while the count left after erasing is read back
+    reading: Arc<AtomicBool>,
+}
+
+impl ErasedParticles {
+    /// Erased particles that the main world doesn't know about yet.
+    fn pending(&self) -> usize {
+        self.erased.load(Ordering::Relaxed) - self.applied.load(Ordering::Relaxed)
+    }
+
+    /// Whether the count left after erasing is still being read back. Particles can't be
+    /// painted in the meantime, since they would end up past the particles left.
+    pub fn reading(&self) -> bool {
+        self.reading.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads back how many particles are left after erasing without waiting for the gpu.
+/// While it is in flight, no more particles are erased and the particles past the
+/// count left are neither simulated nor drawn, see erase_count.
+#[derive(Resource, Default)]
+struct EraseReadback {
+    /// Receives a copy of erase_count, set while a readback is in flight
+    staging: Option<Buffer>,
+    /// settings.particle_count when erasing
+    particle_count: usize,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+fn apply_erased_particles(erased: Res<ErasedParticles>, mut settings: ResMut<SimulationSettings>) {
+    let total = erased.erased.load(Ordering::Relaxed);
+    let pending = total - erased.applied.swap(total, Ordering::Relaxed);
+    if pending > 0 {
+        settings.particle_count = settings.particle_count.saturating_sub(pending);
+    }
 }
 
 #[derive(Resource)]
 pub struct GpuBuffers {
-    /// settings.particle_count >= allocated_particles is ensured in extract_particle_related_things
+    /// Capacity of the particle buffer, see particle_capacity. At least settings.particle_count,
+    /// shrinking the particle count releases the particles above it.
     allocated_particles: usize,
+    /// The previous particle buffer and the size of the particles to copy from it,
+    /// set when the particle buffer was replaced and copied over in ParticleNode
+    resize_copy: Mutex<Option<(Buffer, u64)>>,
     /// settings.particle_count == initialized_particles is ensured in ParticleNode
     initialized_particles: AtomicU32,
+    /// Amount of simulation steps ParticleNode has run, used to derive the seed of a
+    /// step from settings.seed.
+    steps: AtomicU32,
+    /// Sum of the delta times of all steps, see GpuSettings::simulated_time
+    simulated_time: f32,
     waited: u32,
     pub particles: UninitBufferVec<Particle>,
     pub settings: UniformBuffer<GpuSettings>,
     pub sorted_indices: StorageBuffer<Vec<u32>>,
+    /// The linear colors in color_order
+    pub colors: StorageBuffer<Vec<Vec4>>,
+    pub interactions: StorageBuffer<Vec<Interaction>>,
+    pub species: StorageBuffer<Vec<GpuSpecies>>,
+    /// Has a placeholder if there are no obstacles, since bindings can't be empty.
+    /// The actual count is in the settings.
+    pub obstacles: StorageBuffer<Vec<GpuObstacle>>,
+    /// Has a placeholder if there are no force fields, like obstacles
+    pub force_fields: StorageBuffer<Vec<GpuForceField>>,
+    /// Has a placeholder if there are no reactions, like obstacles
+    pub reactions: StorageBuffer<Vec<GpuReaction>>,
+    /// The particles are copied here before erasing, so they can be compacted into particles.
+    /// Only as large as the particle buffer while erasing.
+    pub particles_copy: UninitBufferVec<Particle>,
+    /// Amount of particles left after erasing. u32::MAX while no readback is in flight,
+    /// the particles past it are left over from erasing and neither simulated nor drawn.
+    pub erase_count: StorageBuffer<u32>,
+    /// Neighbour count and force magnitude of each particle from the last step,
+    /// for coloring by them
+    pub particle_fields: UninitBufferVec<Vec2>,
+    /// The color of each particle after the reactions, written by update_velocity
+    /// and applied by update_position, so the colors don't change while they are read
+    pub next_colors: UninitBufferVec<u32>,
+    /// The particle closest to the cursor for the grid debug overlay, see [`GpuProbe`]
+    pub probe: StorageBuffer<GpuProbe>,
 
     // prefix sum buffers. used for calculating the cell offsets
     pub thread_blocks: u32,
@@ -199,16 +554,35 @@ pub struct GpuBuffers {
 }
 
 impl GpuBuffers {
+    /// Amount of simulation steps that were run on the gpu
+    pub fn steps(&self) -> u32 {
+        self.steps.load(Ordering::Relaxed)
+    }
+
     pub fn new() -> Self {
         Self {
             allocated_particles: 0,
+            resize_copy: Mutex::new(None),
             initialized_particles: AtomicU32::new(0),
+            steps: AtomicU32::new(0),
+            simulated_time: 0.,
             waited: 0,
             particles: UninitBufferVec::new(
                 BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
             ),
             settings: UniformBuffer::default(),
             sorted_indices: StorageBuffer::default(),
+            colors: StorageBuffer::default(),
+            interactions: StorageBuffer::default(),
+            species: StorageBuffer::default(),
+            obstacles: StorageBuffer::default(),
+            force_fields: StorageBuffer::default(),
+            reactions: StorageBuffer::default(),
+            particles_copy: UninitBufferVec::new(BufferUsages::STORAGE | BufferUsages::COPY_DST),
+            erase_count: StorageBuffer::default(),
+            particle_fields: UninitBufferVec::new(BufferUsages::STORAGE),
+            next_colors: UninitBufferVec::new(BufferUsages::STORAGE),
+            probe: StorageBuffer::default(),
 
             thread_blocks: 0,
             counter: StorageBuffer::default(),
@@ -227,80 +601,254 @@ fn extract_particle_related_things(
     time: Extract<Res<Time<Virtual>>>,
     mut events_reader: Extract<EventReader<ParticleEvent>>,
     todo: Res<Todo>,
+    snapshots: Res<SnapshotQueue>,
+    brush: Extract<Res<Brush>>,
+    heatmap: Extract<Res<Heatmap>>,
+    erased: Res<ErasedParticles>,
+    mut readback: ResMut<EraseReadback>,
+    delta_time_override: Extract<Res<DeltaTimeOverride>>,
 ) {
+    let mut settings = settings.clone();
+    settings.particle_count = settings.particle_count.saturating_sub(erased.pending());
     commands.insert_resource(settings.clone());
 
+    if let Some(particles) = snapshots.take_load() {
+        info!("Loading snapshot");
+        let mut new_buffer = UninitBufferVec::<Particle>::new(
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        );
+        let capacity = particle_capacity(0, particles.len());
+        for _ in 0..capacity {
+            new_buffer.add();
+        }
+        new_buffer.write_buffer(&device);
+        queue.write_buffer(
+            new_buffer.buffer().unwrap(),
+            0,
+            &particles_to_bytes(&particles),
+        );
+
+        buffers.particles = new_buffer;
+        buffers.allocated_particles = capacity;
+        *buffers.resize_copy.lock().unwrap() = None;
+        buffers
+            .initialized_particles
+            .store(particles.len() as u32, Ordering::Relaxed);
+        buffers.waited = 0;
+        // the particles the readback is about are gone
+        *readback = EraseReadback::default();
+    }
+
+    let mut spawned = Vec::new();
+    let mut erase = None;
     for event in events_reader.read() {
-        match event {
+        match *event {
             ParticleEvent::RandomizePositions => {
                 todo.set_randomize_positions(true);
             }
             ParticleEvent::RandomizeColors => {
                 todo.set_randomize_colors(true);
             }
+            ParticleEvent::Spawn {
+                position,
+                radius,
+                color,
+                count,
+            } => {
+                let bounds = settings.bounds().as_vec2();
+                let mut rng = rand::thread_rng();
+                spawned.extend(particles_in_circle(
+                    position, radius, color, count, bounds, &mut rng,
+                ));
+            }
+            ParticleEvent::Erase { position, radius } => {
+                erase = Some((position, radius));
+            }
         }
     }
 
-    buffers.waited += 1;
-    if buffers.allocated_particles < settings.particle_count {
-        info!("Replacing buffer");
-        let new_particles_offset = buffers.particles.len();
-
-        if new_particles_offset == 0 {
-            buffers.particles.add();
-            buffers.particles.add();
-            buffers.particles.write_buffer(&device);
-        }
+    // one erase at a time, the eraser sends a new event next frame anyway
+    let erase = erase.filter(|_| readback.staging.is_none());
+    if erase.is_some() {
+        readback.staging = Some(device.create_buffer(&BufferDescriptor {
+            label: Some("erase readback"),
+            size: 4,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        readback.particle_count = settings.particle_count;
+    }
+    erased
+        .reading
+        .store(readback.staging.is_some(), Ordering::Relaxed);
 
+    buffers.waited += 1;
+    let count = settings.particle_count;
+    // particles above the count are gone, so growing again initializes new ones
+    buffers
+        .initialized_particles
+        .fetch_min(count as u32, Ordering::Relaxed);
+
+    let capacity = particle_capacity(buffers.allocated_particles, count);
+    if capacity != buffers.allocated_particles {
+        info!("Resizing the particle buffer to {capacity} particles");
         let mut new_buffer = UninitBufferVec::<Particle>::new(
             BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
         );
-        for _ in 0..settings.particle_count {
+        for _ in 0..capacity {
             new_buffer.add();
         }
         new_buffer.write_buffer(&device);
-        let mut command_encoder =
-            device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        command_encoder.copy_buffer_to_buffer(
+
+        // keeping the particles that are still alive, spawned particles are written behind them
+        let kept = buffers.initialized_particles.load(Ordering::Relaxed) as usize;
+        let mut resize_copy = buffers.resize_copy.lock().unwrap();
+        *resize_copy = match (kept, buffers.particles.buffer()) {
+            (1.., Some(old_buffer)) => Some((
+                old_buffer.clone(),
+                (std::mem::size_of::<Particle>() * kept) as u64,
+            )),
+            _ => None,
+        };
+        drop(resize_copy);
+
+        buffers.particles = new_buffer;
+        buffers.allocated_particles = capacity;
+        buffers.waited = 0;
+    }
+
+    // The spawned particles are already included in settings.particle_count.
+    // They are placed right after the initialized particles, in front of the ones
+    // that still have to be initialized.
+    if !spawned.is_empty() {
+        let initialized = buffers.initialized_particles.load(Ordering::Relaxed) as usize;
+        let offset = settings
+            .particle_count
+            .saturating_sub(spawned.len())
+            .min(initialized);
+        spawned.truncate(settings.particle_count - offset);
+        queue.write_buffer(
             buffers.particles.buffer().unwrap(),
-            0,
-            new_buffer.buffer().unwrap(),
-            0,
-            (std::mem::size_of::<Particle>() * new_particles_offset) as u64,
+            (offset * std::mem::size_of::<Particle>()) as u64,
+            &particles_to_bytes(&spawned),
         );
-        queue.submit([command_encoder.finish()]);
-        device.poll(Maintain::Wait);
+        buffers
+            .initialized_particles
+            .store((offset + spawned.len()) as u32, Ordering::Relaxed);
+    }
 
-        buffers.particles = new_buffer;
+    // The copy is only needed while erasing and released afterwards.
+    let copy_len = match erase {
+        Some(_) => buffers.allocated_particles,
+        None => 2,
+    };
+    if buffers.particles_copy.len() != copy_len {
+        let mut new_buffer =
+            UninitBufferVec::<Particle>::new(BufferUsages::STORAGE | BufferUsages::COPY_DST);
+        for _ in 0..copy_len {
+            new_buffer.add();
+        }
+        new_buffer.write_buffer(&device);
+        buffers.particles_copy = new_buffer;
+    }
 
-        buffers.allocated_particles = settings.particle_count;
-        buffers.waited = 0;
+    // recalculated in every step, so there's nothing to keep
+    if buffers.particle_fields.len() != buffers.allocated_particles {
+        let mut new_buffer = UninitBufferVec::<Vec2>::new(BufferUsages::STORAGE);
+        for _ in 0..buffers.allocated_particles {
+            new_buffer.add();
+        }
+        new_buffer.write_buffer(&device);
+        buffers.particle_fields = new_buffer;
+    }
+    if buffers.next_colors.len() != buffers.allocated_particles {
+        let mut new_buffer = UninitBufferVec::<u32>::new(BufferUsages::STORAGE);
+        for _ in 0..buffers.allocated_particles {
+            new_buffer.add();
+        }
+        new_buffer.write_buffer(&device);
+        buffers.next_colors = new_buffer;
     }
 
-    let colors = std::array::from_fn(|i| {
-        let c = COLORS[settings.color_order[i].id as usize].to_u8_array();
-        [
-            linear_f32_from_gamma_u8(c[0]),
-            linear_f32_from_gamma_u8(c[1]),
-            linear_f32_from_gamma_u8(c[2]),
-            linear_f32_from_gamma_u8(c[3]),
-        ]
-        .into()
-    });
-    let matrix = ColorMatrix::new(&settings.matrix);
+    let colors = settings
+        .color_order
+        .iter()
+        .map(|color| {
+            let c = palette_color(color.id).to_u8_array();
+            Vec4::new(
+                linear_f32_from_gamma_u8(c[0]),
+                linear_f32_from_gamma_u8(c[1]),
+                linear_f32_from_gamma_u8(c[2]),
+                linear_f32_from_gamma_u8(c[3]),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut buffer = StorageBuffer::from(colors);
+    buffer.write_buffer(&device, &queue);
+    buffers.colors = buffer;
+
+    let mut buffer = StorageBuffer::from(Interaction::matrix(&settings));
+    buffer.write_buffer(&device, &queue);
+    buffers.interactions = buffer;
+
+    let mut buffer = StorageBuffer::from(GpuSpecies::list(&settings));
+    buffer.write_buffer(&device, &queue);
+    buffers.species = buffer;
+
+    let mut obstacles = GpuObstacle::list(&settings);
+    let obstacle_count = obstacles.len() as u32;
+    if obstacles.is_empty() {
+        obstacles.push(GpuObstacle::default());
+    }
+    let mut buffer = StorageBuffer::from(obstacles);
+    buffer.write_buffer(&device, &queue);
+    buffers.obstacles = buffer;
+
+    let mut force_fields = GpuForceField::list(&settings);
+    let force_field_count = force_fields.len() as u32;
+    if force_fields.is_empty() {
+        force_fields.push(GpuForceField::default());
+    }
+    let mut buffer = StorageBuffer::from(force_fields);
+    buffer.write_buffer(&device, &queue);
+    buffers.force_fields = buffer;
+
+    let mut reactions = GpuReaction::list(&settings);
+    let reaction_count = reactions.len() as u32;
+    if reactions.is_empty() {
+        reactions.push(GpuReaction::default());
+    }
+    let mut buffer = StorageBuffer::from(reactions);
+    buffer.write_buffer(&device, &queue);
+    buffers.reactions = buffer;
+
+    let delta_time = delta_time_override
+        .0
+        .or(settings.fixed_delta_time)
+        .map(|dt| dt * time.effective_speed())
+        .unwrap_or(time.delta_seconds());
+    let simulated_time = buffers.simulated_time;
+    buffers.simulated_time += delta_time;
 
     let gpu_settings = GpuSettings {
         time: time.elapsed_seconds(),
-        delta_time: time.delta_seconds(),
+        delta_time,
+        simulated_time,
         particle_count: settings.particle_count as u32,
         min_distance: settings.min_distance as f32,
         max_distance: settings.max_distance() as f32,
         max_velocity: settings.max_velocity,
         velocity_half_life: settings.velocity_half_life,
         force_factor: settings.force_factor,
+        temperature: settings.temperature,
         bounds: Vec2::new(settings.bounds().x as f32, settings.bounds().y as f32),
         max_attractions: settings.max_attractions,
         acceleration_method: settings.acceleration_method as u32,
+        boundary_mode: settings.boundary_mode as u32,
+        per_pair_radii: settings.per_pair_radii as u32,
+        obstacle_count,
+        force_field_count,
+        reaction_count,
 
         new_particles: (settings.particle_count as i32
             - buffers.initialized_particles.load(Ordering::Relaxed) as i32)
@@ -312,14 +860,29 @@ fn extract_particle_related_things(
         particle_size: settings.particle_size,
         rgb: settings.rgb as u32,
         rgb_speed: settings.rgb_speed,
+        color_mode: settings.color_mode as u32,
+        colormap: settings.colormap as u32,
+        // the shaders divide by it
+        color_max: settings.color_max.max(f32::MIN_POSITIVE),
+        heatmap_opacity: match heatmap.enabled {
+            true => heatmap.opacity,
+            false => 0.,
+        },
+        heatmap_max: heatmap.max_count.max(1) as f32,
+
+        brush_mode: brush.gpu_mode(),
+        brush_position: brush.position.unwrap_or_default(),
+        brush_radius: brush.radius,
+        brush_strength: brush.strength,
+
+        erase_position: erase.map(|(position, _)| position).unwrap_or_default(),
+        erase_radius: erase.map(|(_, radius)| radius).unwrap_or_default(),
 
         cell_count: settings.cell_count(),
-        seed: rand::thread_rng().gen(),
+        seed: step_seed(&settings, buffers.steps.load(Ordering::Relaxed)),
 
         color_count: settings.color_count as u32,
-        max_color_count: COLORS.len() as u32,
-        colors,
-        matrix,
+        max_color_count: settings.palette_size() as u32,
     };
     let mut buffer = UniformBuffer::from(gpu_settings);
     commands.insert_resource(gpu_settings);
@@ -349,6 +912,104 @@ fn extract_particle_related_things(
     let mut buffer = StorageBuffer::from(0u32);
     buffer.write_buffer(&device, &queue);
     buffers.prefix_sum_index = buffer;
+
+    if buffers.probe.buffer().is_none() {
+        let mut buffer = StorageBuffer::from(GpuProbe::default());
+        buffer.add_usages(BufferUsages::COPY_SRC);
+        buffer.write_buffer(&device, &queue);
+        buffers.probe = buffer;
+    }
+
+    if buffers.erase_count.buffer().is_none() {
+        let mut buffer = StorageBuffer::from(u32::MAX);
+        buffer.add_usages(BufferUsages::COPY_SRC | BufferUsages::COPY_DST);
+        buffer.write_buffer(&device, &queue);
+        buffers.erase_count = buffer;
+    } else if readback.staging.is_none() {
+        queue.write_buffer(
+            buffers.erase_count.buffer().unwrap(),
+            0,
+            &u32::MAX.to_le_bytes(),
+        );
+    }
+}
+
+/// The capacity of the particle buffer for `count` particles, given the current `capacity`.
+/// Grows geometrically and only shrinks once less than a quarter of it is used, so
+/// dragging the particle count or painting doesn't replace the buffer every frame.
+/// The buffer must not be empty, so it is at least 2.
+pub fn particle_capacity(capacity: usize, count: usize) -> usize {
+    let capacity = if count > capacity {
+        count.max(capacity * 2)
+    } else if count < capacity / 4 {
+        count * 2
+    } else {
+        capacity
+    };
+    capacity.max(2)
+}
+
+#[test]
+fn test_particle_capacity() {
+    assert_eq!(particle_capacity(0, 0), 2);
+    assert_eq!(particle_capacity(0, 9000), 9000);
+    assert_eq!(particle_capacity(9000, 9001), 18000);
+    assert_eq!(particle_capacity(9000, 30_000), 30_000);
+    assert_eq!(particle_capacity(18000, 9000), 18000);
+    assert_eq!(particle_capacity(18000, 4500), 18000);
+    assert_eq!(particle_capacity(18000, 4000), 8000);
+}
+
+/// Maps the copy of erase_count after ParticleNode erased particles and applies it
+/// once the gpu is done, which is usually a frame or two later.
+fn read_erased_particles(
+    todo: Res<Todo>,
+    buffers: Res<GpuBuffers>,
+    erased: Res<ErasedParticles>,
+    mut readback: ResMut<EraseReadback>,
+    device: Res<RenderDevice>,
+) {
+    let Some(staging) = readback.staging.clone() else {
+        return;
+    };
+    if todo.read_erased() {
+        todo.set_read_erased(false);
+        let mapped = readback.mapped.clone();
+        device.map_buffer(
+            &staging.slice(..),
+            MapMode::Read,
+            move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Relaxed),
+                Err(e) => error!("Failed to map erase readback buffer: {e}"),
+            },
+        );
+        readback.map_requested = true;
+    } else if !readback.map_requested {
+        // ParticleNode didn't get to erase, e.g. because the pipelines weren't ready yet
+        *readback = EraseReadback::default();
+        erased.reading.store(false, Ordering::Relaxed);
+        return;
+    }
+
+    device.poll(Maintain::Poll);
+    if !readback.mapped.load(Ordering::Relaxed) {
+        return;
+    }
+    let bytes = staging.slice(..).get_mapped_range();
+    let remaining = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    drop(bytes);
+    staging.unmap();
+
+    erased.erased.fetch_add(
+        readback.particle_count.saturating_sub(remaining),
+        Ordering::Relaxed,
+    );
+    // particles added by the particle count since erasing are initialized behind the ones left
+    buffers
+        .initialized_particles
+        .fetch_min(remaining as u32, Ordering::Relaxed);
+    *readback = EraseReadback::default();
+    erased.reading.store(false, Ordering::Relaxed);
 }
 
 pub fn linear_f32_from_gamma_u8(s: u8) -> f32 {
@@ -385,7 +1046,7 @@ fn test_manual_rem_euclid() {
     assert_eq!(rem_euclid(-2, 2), 0);
 }
 
-fn surrounding_cells(cell: UVec2, cells: UVec2) -> [u32; 9] {
+pub(crate) fn surrounding_cells(cell: UVec2, cells: UVec2) -> [u32; 9] {
     let minus_x = (cell.x as i32 - 1).rem_euclid(cells.x as i32) as u32;
     let minus_y = (cell.y as i32 - 1).rem_euclid(cells.y as i32) as u32;
     let plus_x = (cell.x as i32 + 1).rem_euclid(cells.x as i32) as u32;
@@ -410,6 +1071,50 @@ fn surrounding_cells(cell: UVec2, cells: UVec2) -> [u32; 9] {
     ]
 }
 
+/// Like [`surrounding_cells`], but without wrapping around the bounds.
+/// Cells outside of the grid are None.
+pub(crate) fn surrounding_cells_unwrapped(cell: UVec2, cells: UVec2) -> [Option<u32>; 9] {
+    let cell = cell.as_ivec2();
+    let cells = cells.as_ivec2();
+    let mut surrounding = [None; 9];
+    for (i, offset) in [-1, 0, 1]
+        .into_iter()
+        .flat_map(|y| [-1, 0, 1].map(|x| IVec2::new(x, y)))
+        .enumerate()
+    {
+        let c = cell + offset;
+        if c.cmpge(IVec2::ZERO).all() && c.cmplt(cells).all() {
+            surrounding[i] = Some((c.x + c.y * cells.x) as u32);
+        }
+    }
+    surrounding
+}
+
+#[test]
+fn test_surrounding_cells_unwrapped() {
+    let cells = UVec2::new(3, 3);
+    let surrounding = surrounding_cells_unwrapped(UVec2::new(0, 0), cells);
+    assert_eq!(
+        surrounding,
+        [
+            None,
+            None,
+            None,
+            None,
+            Some(0),
+            Some(1),
+            None,
+            Some(3),
+            Some(4)
+        ]
+    );
+    let surrounding = surrounding_cells_unwrapped(UVec2::new(1, 1), cells);
+    assert_eq!(
+        surrounding.map(Option::unwrap),
+        surrounding_cells(UVec2::new(1, 1), cells)
+    );
+}
+
 #[derive(Resource)]
 pub struct ParticlePipelines {
     prefix_sum: CachedComputePipelineId,
@@ -421,6 +1126,8 @@ pub struct ParticlePipelines {
     update_position: CachedComputePipelineId,
     randomize_positions: CachedComputePipelineId,
     randomize_colors: CachedComputePipelineId,
+    erase_particles: CachedComputePipelineId,
+    probe_particle: CachedComputePipelineId,
 }
 
 impl FromWorld for ParticlePipelines {
@@ -455,6 +1162,8 @@ impl FromWorld for ParticlePipelines {
         let update_position = new_compute_pipeline("update_position", &shader);
         let randomize_positions = new_compute_pipeline("randomize_positions", &shader);
         let randomize_colors = new_compute_pipeline("randomize_colors", &shader);
+        let erase_particles = new_compute_pipeline("erase_particles", &shader);
+        let probe_particle = new_compute_pipeline("probe_particle", &shader);
 
         ParticlePipelines {
             prefix_sum,
@@ -466,6 +1175,8 @@ impl FromWorld for ParticlePipelines {
             update_position,
             randomize_positions,
             randomize_colors,
+            erase_particles,
+            probe_particle,
         }
     }
 }
@@ -489,6 +1200,17 @@ impl FromWorld for ParticleBindGroupLayouts {
                     storage_buffer::<Vec<u32>>(false),
                     storage_buffer::<Vec<u32>>(false),
                     storage_buffer::<u32>(false),
+                    storage_buffer_read_only::<Vec<Vec4>>(false),
+                    storage_buffer_read_only::<Vec<Interaction>>(false),
+                    storage_buffer_read_only::<Vec<Particle>>(false),
+                    storage_buffer::<u32>(false),
+                    storage_buffer::<Vec<Vec2>>(false),
+                    storage_buffer_read_only::<Vec<GpuObstacle>>(false),
+                    storage_buffer_read_only::<Vec<GpuForceField>>(false),
+                    storage_buffer_read_only::<Vec<GpuSpecies>>(false),
+                    storage_buffer_read_only::<Vec<GpuReaction>>(false),
+                    storage_buffer::<Vec<u32>>(false),
+                    storage_buffer::<GpuProbe>(false),
                 ),
             ),
         );
@@ -507,17 +1229,33 @@ fn prepare_bind_groups(
     render_device: Res<RenderDevice>,
     view_uniforms: Res<ViewUniforms>,
 ) {
+    // there are no views yet, e.g. while the image of a poster camera is being prepared
+    let Some(view_uniforms) = view_uniforms.uniforms.binding() else {
+        commands.remove_resource::<ParticleBindGroups>();
+        return;
+    };
     let bind_group = render_device.create_bind_group(
         None,
         &layouts[0],
         &BindGroupEntries::sequential((
             buffers.particles.binding().unwrap(),
             buffers.settings.binding().unwrap(),
-            view_uniforms.uniforms.binding().unwrap(),
+            view_uniforms,
             buffers.sorted_indices.binding().unwrap(),
             buffers.counter.binding().unwrap(),
             buffers.prefix_sum_reduction.binding().unwrap(),
             buffers.prefix_sum_index.binding().unwrap(),
+            buffers.colors.binding().unwrap(),
+            buffers.interactions.binding().unwrap(),
+            buffers.particles_copy.binding().unwrap(),
+            buffers.erase_count.binding().unwrap(),
+            buffers.particle_fields.binding().unwrap(),
+            buffers.obstacles.binding().unwrap(),
+            buffers.force_fields.binding().unwrap(),
+            buffers.species.binding().unwrap(),
+            buffers.reactions.binding().unwrap(),
+            buffers.next_colors.binding().unwrap(),
+            buffers.probe.binding().unwrap(),
         )),
     );
     commands.insert_resource(ParticleBindGroups([bind_group]));
@@ -536,12 +1274,24 @@ impl render_graph::Node for ParticleNode {
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
         world: &'w World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let bind_groups = world.resource::<ParticleBindGroups>();
+        let buffers = world.resource::<GpuBuffers>();
+        if let Some((old_buffer, size)) = buffers.resize_copy.lock().unwrap().take() {
+            render_context.command_encoder().copy_buffer_to_buffer(
+                &old_buffer,
+                0,
+                buffers.particles.buffer().unwrap(),
+                0,
+                size,
+            );
+        }
+
+        let Some(bind_groups) = world.get_resource::<ParticleBindGroups>() else {
+            return Ok(());
+        };
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<ParticlePipelines>();
         let gpu_settings = world.resource::<GpuSettings>();
         let settings = world.resource::<SimulationSettings>();
-        let buffers = world.resource::<GpuBuffers>();
         let todo = world.resource::<Todo>();
 
         let mut pass = render_context
@@ -567,6 +1317,8 @@ impl render_graph::Node for ParticleNode {
         let update_position = get_pipeline!(update_position);
         let randomize_positions = get_pipeline!(randomize_positions);
         let randomize_colors = get_pipeline!(randomize_colors);
+        let erase_particles = get_pipeline!(erase_particles);
+        let probe_particle = get_pipeline!(probe_particle);
 
         let workgroup_count =
             (settings.particle_count as f32 / WORKGROUP_SIZE as f32).ceil() as u32;
@@ -613,6 +1365,59 @@ impl render_graph::Node for ParticleNode {
         pass.set_pipeline(update_position);
         pass.dispatch_workgroups(workgroup_count, 1, 1);
 
+        buffers.steps.fetch_add(1, Ordering::Relaxed);
+
+        // The probe looks the particle up by its index in the grid, so it runs before erasing
+        // moves the particles around. The result is read back in read_probe.
+        let probe = world.resource::<ProbeReadback>();
+        let probe_requested = probe.requested();
+        if probe_requested {
+            pass.set_pipeline(probe_particle);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        drop(pass);
+
+        if probe_requested {
+            render_context.command_encoder().copy_buffer_to_buffer(
+                buffers.probe.buffer().unwrap(),
+                0,
+                probe.staging.as_ref().unwrap(),
+                0,
+                GpuProbe::min_size().get(),
+            );
+            probe.copied.store(true, Ordering::Relaxed);
+        }
+
+        // Erasing compacts the particles outside of the eraser from the copy back into particles.
+        // The new particle count is read back in read_erased_particles.
+        if gpu_settings.erase_radius > 0. {
+            let readback = world.resource::<EraseReadback>();
+            let command_encoder = render_context.command_encoder();
+            command_encoder.clear_buffer(buffers.erase_count.buffer().unwrap(), 0, None);
+            command_encoder.copy_buffer_to_buffer(
+                buffers.particles.buffer().unwrap(),
+                0,
+                buffers.particles_copy.buffer().unwrap(),
+                0,
+                (std::mem::size_of::<Particle>() * settings.particle_count) as u64,
+            );
+
+            let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_bind_group(0, &bind_groups[0], &[0]);
+            pass.set_pipeline(erase_particles);
+            pass.dispatch_workgroups(workgroup_count, 1, 1);
+            drop(pass);
+
+            command_encoder.copy_buffer_to_buffer(
+                buffers.erase_count.buffer().unwrap(),
+                0,
+                readback.staging.as_ref().unwrap(),
+                0,
+                4,
+            );
+            todo.set_read_erased(true);
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,979 @@
+//! A cpu port of the simulation step in `compute.wgsl`.
+//!
+//! This mirrors the compute shaders pass by pass (including the quirks of the
+//! grid spatial partitioning), so it can be used as an oracle for the shaders
+//! and for testing the physics on machines without a gpu.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    compute::{
+        step_seed, surrounding_cells, surrounding_cells_unwrapped, GpuForceField, GpuObstacle,
+        GpuReaction,
+    },
+    data::{
+        AccelerationMethod, BoundaryMode, ColorId, ObstacleResponse, Particle, SimulationSettings,
+    },
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct CpuSimulation {
+    pub particles: Vec<Particle>,
+    pub sorted_indices: Vec<u32>,
+    // Same layout as the gpu counter buffer: one entry per cell plus
+    // one additional entry containing the total particle count.
+    pub counter: Vec<u32>,
+    /// Sum of the delta times of all steps, moves the noise force fields
+    pub time: f32,
+    /// Amount of steps that were run, the seed of a step is derived from it like on the gpu
+    pub steps: u32,
+}
+
+impl CpuSimulation {
+    pub fn new(particles: Vec<Particle>) -> Self {
+        Self {
+            particles,
+            sorted_indices: Vec::new(),
+            counter: Vec::new(),
+            time: 0.,
+            steps: 0,
+        }
+    }
+
+    /// Spawns settings.particle_count particles uniformly distributed in the bounds,
+    /// like `initialize_particles` does on the gpu.
+    pub fn from_settings(settings: &SimulationSettings, rng: &mut impl Rng) -> Self {
+        let bounds = settings.bounds().as_vec2();
+        let particles = (0..settings.particle_count)
+            .map(|_| Particle {
+                position: (2. * Vec2::new(rng.gen(), rng.gen()) - 1.) * bounds,
+                velocity: Vec2::ZERO,
+                color: ColorId::new(rng.gen_range(0..settings.color_count as u32)),
+                padding: 0,
+            })
+            .collect();
+        Self::new(particles)
+    }
+
+    /// Runs one full simulation step, in the same order as `ParticleNode`.
+    pub fn step(&mut self, settings: &SimulationSettings, delta_time: f32) {
+        self.count_particles(settings);
+        self.cell_offsets();
+        self.sort_particles(settings);
+        self.update_velocity(settings, delta_time);
+        self.update_position(settings, delta_time);
+        self.time += delta_time;
+        self.steps += 1;
+    }
+
+    /// counter[ci] will contain the amount of particles in cell index ci
+    pub fn count_particles(&mut self, settings: &SimulationSettings) {
+        let cell_count = settings.cell_count();
+        self.counter.clear();
+        self.counter
+            .resize((cell_count.x * cell_count.y + 1) as usize, 0);
+
+        for particle in &self.particles {
+            let ci = cell_index(particle.position, settings);
+            self.counter[ci as usize] += 1;
+        }
+    }
+
+    /// Inclusive prefix sum over the counter, equivalent to `prefix_sum.wgsl`.
+    /// counter[ci] will be the count of all particles in the cells with
+    /// cell index smaller than or equal to ci.
+    pub fn cell_offsets(&mut self) {
+        for i in 1..self.counter.len() {
+            self.counter[i] += self.counter[i - 1];
+        }
+    }
+
+    /// counter[ci] will be the starting index of the particles of cell index
+    /// ci in sorted_indices and counter[ci + 1] the end.
+    pub fn sort_particles(&mut self, settings: &SimulationSettings) {
+        self.sorted_indices.clear();
+        self.sorted_indices.resize(self.particles.len(), 0);
+
+        for (index, particle) in self.particles.iter().enumerate() {
+            let ci = cell_index(particle.position, settings) as usize;
+            self.counter[ci] -= 1;
+            self.sorted_indices[self.counter[ci] as usize] = index as u32;
+        }
+    }
+
+    pub fn update_velocity(&mut self, settings: &SimulationSettings, delta_time: f32) {
+        let max_distance = settings.max_distance() as f32;
+        let bounds = settings.bounds().as_vec2();
+        let max_attractions_per_cell = ((settings.max_attractions as f32 / 9.) as u32).max(1);
+        let obstacles = GpuObstacle::list(settings);
+        let force_fields = GpuForceField::list(settings);
+        let seed = step_seed(settings, self.steps);
+        let reactions = GpuReaction::list(settings);
+        let mut next_colors = Vec::with_capacity(self.particles.len());
+
+        for index in 0..self.particles.len() {
+            let particle = self.particles[index];
+            let species = settings.species(particle.color.id as usize);
+            let mut velocity =
+                particle.velocity * 0.5f32.powf(delta_time / species.velocity_half_life);
+            let mut force = Vec2::ZERO;
+            // see reactions_in_range
+            let mut in_range = 0u32;
+
+            let cell = cell_index_2d(particle.position, settings);
+            let surrounding = match settings.boundary_mode {
+                BoundaryMode::Wrap => surrounding_cells(cell, settings.cell_count()).map(Some),
+                _ => surrounding_cells_unwrapped(cell, settings.cell_count()),
+            };
+            for ci in surrounding.into_iter().flatten() {
+                let start = self.counter[ci as usize];
+                let mut end = self.counter[ci as usize + 1];
+
+                // limiting the amount of particle attractions per cell
+                if end - start > max_attractions_per_cell {
+                    end = start + max_attractions_per_cell;
+                }
+
+                for i in start..end {
+                    let other_index = self.sorted_indices[i as usize] as usize;
+                    let other = self.particles[other_index];
+                    let other_position = match settings.boundary_mode {
+                        BoundaryMode::Wrap => closest_wrapped_other_position(
+                            particle.position,
+                            other.position,
+                            bounds,
+                        ),
+                        _ => other.position,
+                    };
+
+                    let relative_position = other_position - particle.position;
+                    let distance_squared = relative_position.length_squared();
+
+                    if other_index != index {
+                        in_range |= reactions_in_range(
+                            &reactions,
+                            particle.color.id,
+                            other.color.id,
+                            distance_squared,
+                        );
+                    }
+
+                    let (min_radius, max_radius) =
+                        settings.radii(particle.color.id as usize, other.color.id as usize);
+
+                    if distance_squared == 0. || distance_squared > max_radius * max_radius {
+                        continue;
+                    }
+
+                    let attraction =
+                        settings.matrix[other.color.id as usize][particle.color.id as usize];
+
+                    let a = acceleration(
+                        settings.acceleration_method,
+                        min_radius / max_radius,
+                        relative_position / max_radius,
+                        attraction,
+                    );
+
+                    force += a * max_radius * settings.force_factor;
+                }
+            }
+
+            if settings.boundary_mode == BoundaryMode::SoftWall {
+                let a = wall_acceleration(particle.position, settings);
+                force += a * max_distance * settings.force_factor;
+            }
+
+            let a = obstacle_acceleration(particle.position, &obstacles, settings);
+            force += a * max_distance * settings.force_factor;
+
+            velocity += force / species.mass * delta_time;
+            // force fields accelerate all species the same, like gravity
+            velocity +=
+                force_field_acceleration(particle.position, &force_fields, self.time, settings)
+                    * delta_time;
+            if settings.temperature > 0. {
+                // heavier particles jiggle less, like molecules of the same temperature
+                let kick = brownian_kick(seed, index as u32, settings.temperature, delta_time);
+                velocity += kick / species.mass.sqrt();
+            }
+            velocity = velocity.clamp_length_max(species.max_velocity);
+
+            self.particles[index].velocity = velocity;
+            next_colors.push(react(
+                &reactions,
+                seed,
+                index as u32,
+                particle.color.id,
+                in_range,
+                delta_time,
+            ));
+        }
+
+        // applied after all particles reacted, like update_position does on the gpu
+        for (particle, color) in self.particles.iter_mut().zip(next_colors) {
+            particle.color = ColorId::new(color);
+        }
+    }
+
+    pub fn update_position(&mut self, settings: &SimulationSettings, delta_time: f32) {
+        let bounds = settings.bounds().as_vec2();
+        let obstacles = GpuObstacle::list(settings);
+
+        for particle in &mut self.particles {
+            particle.position += particle.velocity * delta_time;
+
+            // moving out of the obstacles and bouncing off of them
+            for obstacle in &obstacles {
+                if obstacle.response != ObstacleResponse::Collide as u32 {
+                    continue;
+                }
+                let (distance, normal) = obstacle_distance(obstacle, particle.position);
+                if distance < 0. {
+                    particle.position -= distance * normal;
+                    particle.velocity -= 2. * particle.velocity.dot(normal).min(0.) * normal;
+                }
+            }
+
+            let p = *particle;
+            let outside = p.position.abs().cmpgt(bounds);
+            match settings.boundary_mode {
+                BoundaryMode::Wrap => {
+                    if p.position.x > bounds.x {
+                        particle.position.x -= 2. * bounds.x;
+                    } else if p.position.x < -bounds.x {
+                        particle.position.x += 2. * bounds.x;
+                    }
+                    if p.position.y > bounds.y {
+                        particle.position.y -= 2. * bounds.y;
+                    } else if p.position.y < -bounds.y {
+                        particle.position.y += 2. * bounds.y;
+                    }
+                }
+                BoundaryMode::Reflect => {
+                    // mirroring the part that went past the bounds
+                    let reflected = p.position.signum() * 2. * bounds - p.position;
+                    particle.position =
+                        Vec2::select(outside, reflected, p.position).clamp(-bounds, bounds);
+                    particle.velocity = Vec2::select(outside, -p.velocity, p.velocity);
+                }
+                BoundaryMode::Clamp | BoundaryMode::SoftWall => {
+                    particle.position = p.position.clamp(-bounds, bounds);
+                    particle.velocity = Vec2::select(outside, Vec2::ZERO, p.velocity);
+                }
+            }
+        }
+    }
+}
+
+pub fn cell_index(position: Vec2, settings: &SimulationSettings) -> u32 {
+    let cell = cell_index_2d(position, settings);
+    cell.x + cell.y * settings.cell_count().x
+}
+
+pub fn cell_index_2d(position: Vec2, settings: &SimulationSettings) -> UVec2 {
+    // moving the position from [-bounds, bounds] to [0, 2 * bounds];
+    let p = settings.bounds().as_vec2() + position;
+    // particles exactly on the upper bounds still belong to the last cell
+    (p / settings.max_distance() as f32)
+        .floor()
+        .as_uvec2()
+        .min(settings.cell_count() - 1)
+}
+
+/// Repulsion of the soft walls, relative to max_distance like [`acceleration`].
+/// A wall pushes the same way a particle closer than min_distance does.
+pub fn wall_acceleration(position: Vec2, settings: &SimulationSettings) -> Vec2 {
+    let bounds = settings.bounds().as_vec2();
+    let rmin = (settings.min_distance as f32).max(1.);
+    let to_lower = position + bounds;
+    let to_upper = bounds - position;
+    (1. - to_lower / rmin).max(Vec2::ZERO) - (1. - to_upper / rmin).max(Vec2::ZERO)
+}
+
+/// The signed distance from the surface of the obstacle, negative inside of it,
+/// and the direction out of the obstacle.
+pub fn obstacle_distance(obstacle: &GpuObstacle, position: Vec2) -> (f32, Vec2) {
+    let offset = match obstacle.kind {
+        GpuObstacle::RECTANGLE => {
+            let p = position - obstacle.a;
+            let q = p.abs() - obstacle.b;
+            let side = Vec2::select(p.cmplt(Vec2::ZERO), Vec2::splat(-1.), Vec2::ONE);
+            if q.x <= 0. && q.y <= 0. {
+                // inside, the closest edge
+                return match q.x > q.y {
+                    true => (q.x, Vec2::new(side.x, 0.)),
+                    false => (q.y, Vec2::new(0., side.y)),
+                };
+            }
+            let outside = q.max(Vec2::ZERO);
+            let distance = outside.length();
+            return (distance, side * outside / distance);
+        }
+        GpuObstacle::SEGMENT => {
+            let pa = position - obstacle.a;
+            let ba = obstacle.b - obstacle.a;
+            let h = (pa.dot(ba) / ba.dot(ba).max(1e-6)).clamp(0., 1.);
+            pa - ba * h
+        }
+        // GpuObstacle::CIRCLE
+        _ => position - obstacle.a,
+    };
+    let distance = offset.length();
+    if distance == 0. {
+        return (-obstacle.radius, Vec2::Y);
+    }
+    (distance - obstacle.radius, offset / distance)
+}
+
+/// Repulsion of the obstacles that repel, like [`wall_acceleration`].
+pub fn obstacle_acceleration(
+    position: Vec2,
+    obstacles: &[GpuObstacle],
+    settings: &SimulationSettings,
+) -> Vec2 {
+    let rmin = (settings.min_distance as f32).max(1.);
+    obstacles
+        .iter()
+        .filter(|obstacle| obstacle.response == ObstacleResponse::Repel as u32)
+        .map(|obstacle| {
+            let (distance, normal) = obstacle_distance(obstacle, position);
+            (1. - distance / rmin).max(0.) * normal
+        })
+        .sum()
+}
+
+/// The acceleration from all force fields at the simulated time.
+pub fn force_field_acceleration(
+    position: Vec2,
+    force_fields: &[GpuForceField],
+    time: f32,
+    settings: &SimulationSettings,
+) -> Vec2 {
+    let mut a = Vec2::ZERO;
+    for field in force_fields {
+        match field.kind {
+            GpuForceField::GRAVITY => a += field.vector,
+            GpuForceField::NOISE => {
+                a += field.strength * curl_noise(position / field.radius, time * field.speed)
+            }
+            GpuForceField::ATTRACTOR | GpuForceField::VORTEX => {
+                // like the brush
+                let center = match settings.boundary_mode {
+                    BoundaryMode::Wrap => closest_wrapped_other_position(
+                        position,
+                        field.vector,
+                        settings.bounds().as_vec2(),
+                    ),
+                    _ => field.vector,
+                };
+                let dpos = center - position;
+                let dist = dpos.length();
+                if dist == 0. || dist > field.radius {
+                    continue;
+                }
+                let dir = dpos / dist;
+                let force = field.strength * (1. - dist / field.radius);
+                a += match field.kind {
+                    GpuForceField::ATTRACTOR => dir * force,
+                    _ => Vec2::new(-dir.y, dir.x) * force,
+                };
+            }
+            _ => {}
+        }
+    }
+    a
+}
+
+/// Divergence free flow, the curl of value noise over the position at time t.
+pub fn curl_noise(p: Vec2, t: f32) -> Vec2 {
+    let e = 0.01;
+    let dx = value_noise(Vec3::new(p.x + e, p.y, t)) - value_noise(Vec3::new(p.x - e, p.y, t));
+    let dy = value_noise(Vec3::new(p.x, p.y + e, t)) - value_noise(Vec3::new(p.x, p.y - e, t));
+    Vec2::new(dy, -dx) / (2. * e)
+}
+
+/// Smoothly interpolated random values at the integer points, in [0, 1].
+fn value_noise(p: Vec3) -> f32 {
+    let i = p.floor().as_ivec3();
+    let f = p - p.floor();
+    let u = f * f * (3. - 2. * f);
+    let value = |x, y, z| lattice_value(i + IVec3::new(x, y, z));
+    let x00 = value(0, 0, 0).lerp(value(1, 0, 0), u.x);
+    let x10 = value(0, 1, 0).lerp(value(1, 1, 0), u.x);
+    let x01 = value(0, 0, 1).lerp(value(1, 0, 1), u.x);
+    let x11 = value(0, 1, 1).lerp(value(1, 1, 1), u.x);
+    x00.lerp(x10, u.y).lerp(x01.lerp(x11, u.y), u.z)
+}
+
+fn lattice_value(p: IVec3) -> f32 {
+    let h = pcg_hash(p.x as u32 ^ pcg_hash(p.y as u32 ^ pcg_hash(p.z as u32)));
+    h as f32 / 4294967295.
+}
+
+/// A random velocity change with a standard deviation of
+/// temperature * sqrt(delta_time) per axis, see [`SimulationSettings::temperature`].
+pub fn brownian_kick(seed: u32, index: u32, temperature: f32, delta_time: f32) -> Vec2 {
+    let a = pcg_hash(seed ^ pcg_hash(index));
+    let b = pcg_hash(a);
+    // Box-Muller transform of two uniform random numbers into two normally distributed ones
+    let radius = (-2. * (a as f32 / 4294967295.).max(1e-7).ln()).sqrt();
+    let angle = 2. * PI * b as f32 / 4294967295.;
+    let normal = radius * Vec2::new(angle.cos(), angle.sin());
+    normal * temperature * delta_time.sqrt()
+}
+
+/// Bit r is set if the catalyst of reaction r is a particle of color `other`
+/// at the squared distance for a particle of color `color`.
+pub fn reactions_in_range(
+    reactions: &[GpuReaction],
+    color: u32,
+    other: u32,
+    distance_squared: f32,
+) -> u32 {
+    let mut in_range = 0;
+    for (r, reaction) in reactions.iter().enumerate() {
+        if reaction.color == color
+            && reaction.catalyst == other
+            && distance_squared <= reaction.radius * reaction.radius
+        {
+            in_range |= 1 << r;
+        }
+    }
+    in_range
+}
+
+/// The color of the particle after the reactions whose catalysts are in range,
+/// the first reaction that happens wins.
+pub fn react(
+    reactions: &[GpuReaction],
+    seed: u32,
+    index: u32,
+    color: u32,
+    in_range: u32,
+    delta_time: f32,
+) -> u32 {
+    let a = pcg_hash(seed ^ pcg_hash(index));
+    for (r, reaction) in reactions.iter().enumerate() {
+        if in_range & (1 << r) == 0 {
+            continue;
+        }
+        let random = pcg_hash(a.wrapping_add(r as u32 + 1)) as f32 / 4294967295.;
+        if random < reaction_chance(reaction.probability, delta_time) {
+            return reaction.product;
+        }
+    }
+    color
+}
+
+/// The chance that a reaction with the probability per second happens in a step.
+/// Nothing happens while paused, even at a probability of 1.
+fn reaction_chance(probability: f32, delta_time: f32) -> f32 {
+    if delta_time <= 0. {
+        return 0.;
+    }
+    if probability >= 1. {
+        return 1.;
+    }
+    1. - (1. - probability.max(0.)).powf(delta_time)
+}
+
+fn pcg_hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub fn closest_wrapped_other_position(pos: Vec2, other_pos: Vec2, bounds: Vec2) -> Vec2 {
+    let mut other = other_pos;
+
+    let wrapped = Vec2::new(
+        if other_pos.x > 0. {
+            other.x - 2. * bounds.x
+        } else {
+            other.x + 2. * bounds.x
+        },
+        if other_pos.y > 0. {
+            other.y - 2. * bounds.y
+        } else {
+            other.y + 2. * bounds.y
+        },
+    );
+
+    if (pos.x - wrapped.x).abs() < (pos.x - other.x).abs() {
+        other.x = wrapped.x;
+    }
+    if (pos.y - wrapped.y).abs() < (pos.y - other.y).abs() {
+        other.y = wrapped.y;
+    }
+
+    other
+}
+
+/// `rmin` and `dpos` are relative to max_distance.
+pub fn acceleration(method: AccelerationMethod, rmin: f32, dpos: Vec2, a: f32) -> Vec2 {
+    match method {
+        AccelerationMethod::R1 => acceleration1(rmin, dpos, a),
+        AccelerationMethod::R2 => acceleration2(rmin, dpos, a),
+        AccelerationMethod::R3 => acceleration3(rmin, dpos, a),
+        AccelerationMethod::Deg90 => acceleration90(dpos, a),
+        AccelerationMethod::Attr => acceleration_attr(dpos, a),
+        AccelerationMethod::Planets => planets(dpos),
+    }
+}
+
+fn acceleration1(rmin: f32, dpos: Vec2, a: f32) -> Vec2 {
+    let dist = dpos.length();
+    let force = if dist < rmin {
+        // always push away. goes from -1 to 0 for dist 0 to rmin
+        dist / rmin - 1.
+    } else {
+        a * (1. - (1. + rmin - 2. * dist).abs() / (1. - rmin))
+    };
+    dpos * force / dist
+}
+
+fn acceleration2(rmin: f32, dpos: Vec2, a: f32) -> Vec2 {
+    acceleration1(rmin, dpos, a) / dpos.length()
+}
+
+fn acceleration3(rmin: f32, dpos: Vec2, a: f32) -> Vec2 {
+    let dist = dpos.length();
+    acceleration1(rmin, dpos, a) / (dist * dist)
+}
+
+fn acceleration90(dpos: Vec2, a: f32) -> Vec2 {
+    let dist = dpos.length();
+    let force = a * (1. - dist);
+    Vec2::new(-dpos.y, dpos.x) * force / dist
+}
+
+fn acceleration_attr(dpos: Vec2, a: f32) -> Vec2 {
+    let dist = dpos.length();
+    let force = 1. - dist;
+    let angle = -a * PI;
+    Vec2::new(
+        angle.cos() * dpos.x + angle.sin() * dpos.y,
+        -angle.sin() * dpos.x + angle.cos() * dpos.y,
+    ) * force
+        / dist
+}
+
+fn planets(dpos: Vec2) -> Vec2 {
+    let dist = dpos.length().max(0.01);
+    dpos * 0.01 / (dist * dist * dist)
+}
+
+#[cfg(test)]
+fn particle(position: Vec2, color: u32) -> Particle {
+    Particle {
+        position,
+        color: ColorId::new(color),
+        ..default()
+    }
+}
+
+#[test]
+fn test_acceleration1_repels_below_min_distance() {
+    let a = acceleration(AccelerationMethod::R1, 0.2, Vec2::new(0.1, 0.), 1.);
+    assert!((a.x - -0.5).abs() < 1e-6);
+    assert_eq!(a.y, 0.);
+
+    // peak attraction is halfway between rmin and 1
+    let a = acceleration(AccelerationMethod::R1, 0.2, Vec2::new(0.6, 0.), 0.5);
+    assert!((a.x - 0.5).abs() < 1e-6);
+
+    let a = acceleration(AccelerationMethod::R1, 0.2, Vec2::new(1., 0.), 1.);
+    assert!(a.x.abs() < 1e-6);
+}
+
+#[test]
+fn test_closest_wrapped_other_position() {
+    let bounds = Vec2::new(100., 50.);
+    let other = closest_wrapped_other_position(Vec2::new(90., 0.), Vec2::new(-90., 0.), bounds);
+    assert_eq!(other, Vec2::new(110., 0.));
+
+    let other = closest_wrapped_other_position(Vec2::new(0., -45.), Vec2::new(0., 45.), bounds);
+    assert_eq!(other, Vec2::new(0., -55.));
+
+    let other = closest_wrapped_other_position(Vec2::new(10., 10.), Vec2::new(20., 20.), bounds);
+    assert_eq!(other, Vec2::new(20., 20.));
+}
+
+#[test]
+fn test_sort_particles() {
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    let bounds = settings.bounds().as_vec2();
+    let mut simulation = CpuSimulation::new(vec![
+        particle(Vec2::new(bounds.x - 1., bounds.y - 1.), 0),
+        particle(-bounds + 1., 0),
+        particle(Vec2::ZERO, 0),
+        particle(-bounds + 2., 0),
+    ]);
+
+    simulation.count_particles(&settings);
+    simulation.cell_offsets();
+    simulation.sort_particles(&settings);
+
+    assert_eq!(*simulation.counter.last().unwrap(), 4);
+    assert_eq!(&simulation.sorted_indices[0..2], &[3, 1]);
+    for (index, particle) in simulation.particles.iter().enumerate() {
+        let ci = cell_index(particle.position, &settings) as usize;
+        let (start, end) = (simulation.counter[ci], simulation.counter[ci + 1]);
+        assert!((start..end).any(|i| simulation.sorted_indices[i as usize] == index as u32));
+    }
+}
+
+#[test]
+fn test_step_wraps_across_bounds() {
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    settings.reset_attractions();
+    settings.matrix[0][0] = 1.;
+    let bounds = settings.bounds().as_vec2();
+
+    // two particles attracting each other across the x boundary
+    let mut simulation = CpuSimulation::new(vec![
+        particle(Vec2::new(bounds.x - 50., 0.), 0),
+        particle(Vec2::new(-bounds.x + 50., 0.), 0),
+    ]);
+    simulation.step(&settings, 1. / 60.);
+
+    assert!(simulation.particles[0].velocity.x > 0.);
+    assert!(simulation.particles[1].velocity.x < 0.);
+
+    simulation.particles[0].velocity = Vec2::new(6000., 0.);
+    simulation.update_position(&settings, 1. / 60.);
+    assert!(simulation.particles[0].position.x < -bounds.x + 60.);
+}
+
+#[test]
+fn test_step_without_wrapping() {
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    settings.reset_attractions();
+    settings.matrix[0][0] = 1.;
+    let bounds = settings.bounds().as_vec2();
+
+    for mode in [
+        BoundaryMode::Reflect,
+        BoundaryMode::Clamp,
+        BoundaryMode::SoftWall,
+    ] {
+        settings.boundary_mode = mode;
+        let mut simulation = CpuSimulation::new(vec![
+            particle(Vec2::new(bounds.x - 100., 0.), 0),
+            particle(Vec2::new(-bounds.x + 100., 0.), 0),
+        ]);
+        simulation.step(&settings, 1. / 60.);
+
+        // no attraction across the bounds
+        assert_eq!(simulation.particles[0].velocity, Vec2::ZERO);
+        assert_eq!(simulation.particles[1].velocity, Vec2::ZERO);
+
+        simulation.particles[0].velocity = Vec2::new(12000., 0.);
+        simulation.update_position(&settings, 1. / 60.);
+        let p = simulation.particles[0];
+        match mode {
+            BoundaryMode::Reflect => {
+                assert_eq!(p.position.x, bounds.x - 100.);
+                assert_eq!(p.velocity.x, -12000.);
+            }
+            _ => {
+                assert_eq!(p.position.x, bounds.x);
+                assert_eq!(p.velocity.x, 0.);
+            }
+        }
+    }
+
+    settings.boundary_mode = BoundaryMode::SoftWall;
+    let a = wall_acceleration(Vec2::new(-bounds.x + 10., bounds.y), &settings);
+    assert!(a.x > 0.);
+    assert!(a.y < 0.);
+}
+
+#[test]
+fn test_per_pair_radii() {
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    settings.reset_attractions();
+    settings.matrix[0][0] = 1.;
+    settings.per_pair_radii = true;
+    settings.color_count = 1;
+
+    let step = |settings: &SimulationSettings| {
+        let mut simulation = CpuSimulation::new(vec![
+            particle(Vec2::new(-150., 0.), 0),
+            particle(Vec2::new(150., 0.), 0),
+        ]);
+        simulation.step(settings, 1. / 60.);
+        simulation.particles[0].velocity
+    };
+
+    // 300 apart is out of the default max radius
+    settings.update_max_distance_from_radii();
+    assert_eq!(step(&settings), Vec2::ZERO);
+
+    settings.max_radius[0][0] = 500.;
+    settings.update_max_distance_from_radii();
+    assert_eq!(settings.max_distance(), 500);
+    assert!(step(&settings).x > 0.);
+}
+
+#[test]
+fn test_per_species() {
+    use crate::data::ForceField;
+
+    let mut settings = SimulationSettings::default();
+    settings.reset_attractions();
+    settings.velocity_half_life = 1000.;
+    settings.force_fields = vec![ForceField::Gravity {
+        acceleration: Vec2::new(0., -600.),
+    }];
+    settings.color_count = 2;
+    // color 0 is attracted by color 1
+    settings.matrix[1][0] = 1.;
+    settings.reset_species();
+    settings.species[0].mass = 4.;
+    settings.species[1].max_velocity = 5.;
+
+    let step = |settings: &SimulationSettings| {
+        let mut simulation = CpuSimulation::new(vec![
+            particle(Vec2::new(-500., 0.), 0),
+            particle(Vec2::new(-400., 0.), 1),
+            particle(Vec2::new(500., 0.), 1),
+        ]);
+        simulation.step(settings, 1. / 60.);
+        (
+            simulation.particles[0].velocity,
+            simulation.particles[2].velocity,
+        )
+    };
+
+    // the species are ignored without per_species
+    let (light, capped) = step(&settings);
+    assert!(light.x > 0.);
+    assert!((light.y + 10.).abs() < 0.01);
+    assert!((capped.y + 10.).abs() < 0.01);
+
+    // the attraction moves the heavy particle less, but it falls just as fast
+    settings.per_species = true;
+    let (heavy, capped) = step(&settings);
+    assert!((heavy.x - light.x / 4.).abs() < 0.01);
+    assert!((heavy.y + 10.).abs() < 0.01);
+    assert!((capped.length() - 5.).abs() < 0.001);
+}
+
+#[test]
+fn test_reactions() {
+    use crate::data::Reaction;
+
+    let mut settings = SimulationSettings::default();
+    settings.reset_attractions();
+    settings.color_count = 3;
+    let reaction = |color, catalyst, product, probability| Reaction {
+        color,
+        catalyst,
+        product,
+        radius: 100.,
+        probability,
+    };
+    settings.reactions = vec![
+        reaction(0, 1, 1, 1.),
+        reaction(1, 0, 0, 1.),
+        reaction(2, 2, 0, 0.),
+    ];
+
+    let colors = |settings: &SimulationSettings| {
+        let mut simulation = CpuSimulation::new(vec![
+            particle(Vec2::new(0., 0.), 0),
+            particle(Vec2::new(50., 0.), 1),
+            particle(Vec2::new(500., 0.), 0),
+            particle(Vec2::new(-500., 0.), 2),
+            particle(Vec2::new(-450., 0.), 2),
+        ]);
+        simulation.step(settings, 1. / 60.);
+        simulation
+            .particles
+            .iter()
+            .map(|p| p.color.id)
+            .collect::<Vec<_>>()
+    };
+
+    // the first two swap colors, since reactions see the colors from before the step
+    assert_eq!(colors(&settings), [1, 0, 0, 2, 2]);
+
+    // a particle doesn't catalyze its own reaction
+    settings.reactions = vec![reaction(0, 0, 1, 1.)];
+    assert_eq!(colors(&settings), [0, 1, 0, 2, 2]);
+
+    // about half of the particles react at a probability of 0.5 per second
+    settings.reactions = vec![reaction(0, 1, 2, 0.5)];
+    let reactions = GpuReaction::list(&settings);
+    let reacted = (0..10_000)
+        .filter(|&i| react(&reactions, 1234, i, 0, 1, 1.) == 2)
+        .count();
+    assert!((4800..5200).contains(&reacted));
+    assert_eq!(react(&reactions, 1234, 0, 0, 0, 1.), 0);
+}
+
+#[test]
+fn test_reactions_paused() {
+    use crate::data::Reaction;
+
+    let mut settings = SimulationSettings::default();
+    settings.reset_attractions();
+    settings.color_count = 2;
+    settings.reactions = vec![Reaction {
+        color: 0,
+        catalyst: 1,
+        product: 1,
+        radius: 100.,
+        probability: 1.,
+    }];
+
+    let reactions = GpuReaction::list(&settings);
+    assert!((0..1000).all(|i| react(&reactions, 1234, i, 0, 1, 0.) == 0));
+
+    let mut simulation = CpuSimulation::new(vec![
+        particle(Vec2::new(0., 0.), 0),
+        particle(Vec2::new(50., 0.), 1),
+    ]);
+    simulation.step(&settings, 0.);
+    assert_eq!(simulation.particles[0].color.id, 0);
+    simulation.step(&settings, 1. / 60.);
+    assert_eq!(simulation.particles[0].color.id, 1);
+}
+
+#[test]
+fn test_obstacles() {
+    use crate::data::{Obstacle, ObstacleShape};
+
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    settings.reset_attractions();
+    settings.obstacles = vec![
+        Obstacle {
+            shape: ObstacleShape::Circle {
+                center: Vec2::ZERO,
+                radius: 100.,
+            },
+            response: ObstacleResponse::Collide,
+        },
+        Obstacle {
+            shape: ObstacleShape::Polyline {
+                points: vec![Vec2::new(300., -200.), Vec2::new(300., 200.)],
+                thickness: 20.,
+            },
+            response: ObstacleResponse::Repel,
+        },
+    ];
+    let obstacles = GpuObstacle::list(&settings);
+    assert_eq!(obstacles.len(), 2);
+
+    let rectangle = GpuObstacle {
+        kind: 1,
+        a: Vec2::new(10., 0.),
+        b: Vec2::new(50., 20.),
+        ..default()
+    };
+    assert_eq!(
+        obstacle_distance(&rectangle, Vec2::new(10., 30.)),
+        (10., Vec2::Y)
+    );
+    assert_eq!(
+        obstacle_distance(&rectangle, Vec2::new(-35., 0.)),
+        (-5., Vec2::NEG_X)
+    );
+
+    // a particle moving into the circle bounces off of it
+    let mut simulation = CpuSimulation::new(vec![particle(Vec2::new(-110., 0.), 0)]);
+    simulation.particles[0].velocity = Vec2::new(1200., 0.);
+    simulation.update_position(&settings, 1. / 60.);
+    let p = simulation.particles[0];
+    assert!((p.position.x - -100.).abs() < 1e-3);
+    assert_eq!(p.velocity, Vec2::new(-1200., 0.));
+
+    // the wall pushes particles close to it away
+    let a = obstacle_acceleration(Vec2::new(280., 0.), &obstacles, &settings);
+    assert!(a.x < 0.);
+    assert_eq!(a.y, 0.);
+    assert_eq!(
+        obstacle_acceleration(Vec2::new(200., 0.), &obstacles, &settings),
+        Vec2::ZERO
+    );
+}
+
+#[test]
+fn test_force_fields() {
+    use crate::data::ForceField;
+
+    let mut settings = SimulationSettings::default();
+    settings.update_bounds(UVec2::new(1000, 500));
+    settings.force_fields = vec![
+        ForceField::Gravity {
+            acceleration: Vec2::new(0., -100.),
+        },
+        ForceField::Vortex {
+            center: Vec2::new(900., 0.),
+            strength: 200.,
+            radius: 300.,
+        },
+    ];
+    let fields = GpuForceField::list(&settings);
+    let a = |position| force_field_acceleration(position, &fields, 0., &settings);
+    assert_eq!(a(Vec2::ZERO), Vec2::new(0., -100.));
+    // the vortex reaches across the wrapped bounds
+    let wrapped = a(Vec2::new(-900., 0.));
+    assert!(wrapped.x.abs() < 1e-3);
+    assert!(wrapped.y < -150.);
+    settings.boundary_mode = BoundaryMode::Reflect;
+    let a = force_field_acceleration(Vec2::new(-900., 0.), &fields, 0., &settings);
+    assert_eq!(a, Vec2::new(0., -100.));
+
+    // the noise flow has no divergence and changes over time
+    let p = Vec2::new(3.3, -1.7);
+    let e = 0.05;
+    let divergence = (curl_noise(p + Vec2::X * e, 0.5).x - curl_noise(p - Vec2::X * e, 0.5).x
+        + curl_noise(p + Vec2::Y * e, 0.5).y
+        - curl_noise(p - Vec2::Y * e, 0.5).y)
+        / (2. * e);
+    assert!(divergence.abs() < 0.1);
+    assert_ne!(curl_noise(p, 0.5), curl_noise(p, 1.5));
+}
+
+#[test]
+fn test_brownian_kick() {
+    let count = 20_000;
+    let kicks: Vec<Vec2> = (0..count)
+        .map(|i| brownian_kick(1234, i, 100., 0.04))
+        .collect();
+    let mean = kicks.iter().sum::<Vec2>() / count as f32;
+    let variance = kicks.iter().map(|k| (*k - mean).powf(2.)).sum::<Vec2>() / count as f32;
+    // standard deviation of 100 * sqrt(0.04) = 20 per axis
+    assert!(mean.abs().max_element() < 0.5);
+    assert!((variance.x.sqrt() - 20.).abs() < 0.5);
+    assert!((variance.y.sqrt() - 20.).abs() < 0.5);
+    assert_ne!(
+        brownian_kick(1234, 0, 100., 0.04),
+        brownian_kick(1235, 0, 100., 0.04)
+    );
+
+    // seeded runs get the same kicks
+    let mut settings = SimulationSettings::default();
+    settings.reset_attractions();
+    settings.temperature = 100.;
+    settings.seed = Some(7);
+    let run = |settings: &SimulationSettings| {
+        let mut simulation = CpuSimulation::new(vec![particle(Vec2::ZERO, 0)]);
+        simulation.step(settings, 1. / 60.);
+        simulation.step(settings, 1. / 60.);
+        simulation.particles[0].velocity
+    };
+    assert_eq!(run(&settings), run(&settings));
+    assert_ne!(run(&settings), Vec2::ZERO);
+}
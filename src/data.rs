@@ -1,9 +1,11 @@
-use bevy::color::{palettes::tailwind, Srgba};
+use bevy::color::{palettes::tailwind, Hsla, Srgba};
 use bevy::prelude::*;
 use bevy::render::render_resource::ShaderType;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 pub const COLORS: &[Srgba] = &[
     tailwind::EMERALD_600,
@@ -24,11 +26,221 @@ pub const COLORS: &[Srgba] = &[
     tailwind::YELLOW_600,
 ];
 
+/// Version of the serialized settings. When the layout changes, this has to be
+/// increased and a migration from the previous version added to [`MIGRATIONS`].
+/// New fields don't need a migration, they get a serde default instead.
+pub const SETTINGS_VERSION: u64 = 1;
+
+/// `MIGRATIONS[v]` turns settings json of version v into version v + 1.
+/// Settings without a version are version 0.
+const MIGRATIONS: [fn(&mut Map<String, Value>); SETTINGS_VERSION as usize] = [migrate_v0];
+
+/// Settings from before versioning. Particles always wrapped around the bounds
+/// and there were no per pair radii, the radii matrices are filled in later.
+fn migrate_v0(settings: &mut Map<String, Value>) {
+    settings
+        .entry("boundary_mode")
+        .or_insert(Value::from("Wrap"));
+    settings
+        .entry("per_pair_radii")
+        .or_insert(Value::from(false));
+}
+
+/// Upper end of the color count slider. More colors are possible,
+/// but the ui gets unwieldy.
+pub const MAX_COLOR_COUNT: usize = 64;
+
+/// The shaders keep track of the reactions that can happen to a particle in the bits of a u32.
+pub const MAX_REACTIONS: usize = 32;
+
+/// Most colors that settings can be loaded with. The matrices have an entry for each
+/// pair of colors, so settings with a lot more would run out of memory.
+pub const MAX_PALETTE_SIZE: usize = 1024;
+
+/// Most grid cells that settings can be loaded with, the gpu keeps a counter for each.
+pub const MAX_CELL_COUNT: u64 = 1 << 24;
+
+/// The color of a palette id. Ids beyond [`COLORS`] are generated by
+/// spreading hues with the golden angle.
+pub fn palette_color(id: u32) -> Srgba {
+    match COLORS.get(id as usize) {
+        Some(color) => *color,
+        None => Hsla::hsl((id as f32 * 137.508) % 360., 0.7, 0.5).into(),
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum Shape {
     #[default]
     Circle = 0,
     Square = 1,
+    /// Soft radial falloff, looks best with additive blending and bloom.
+    /// Drawn additively with the opaque blend mode.
+    Glow = 2,
+}
+
+/// How particles are blended with what's behind them.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Particles cover each other
+    #[default]
+    Opaque,
+    Alpha,
+    /// Overlapping particles add up, so dense clusters get brighter
+    Additive,
+}
+
+/// What the color of a particle shows. Everything except the species
+/// is mapped through the colormap.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorMode {
+    #[default]
+    Species = 0,
+    Speed = 1,
+    /// Direction of the velocity
+    Heading = 2,
+    /// Particles within the max radius. Only the particles that are checked for
+    /// attractions are counted, so it is capped by max_attractions in crowded cells.
+    Neighbours = 3,
+    /// Magnitude of the acceleration from other particles, walls and the brush
+    Force = 4,
+}
+
+impl ColorMode {
+    /// A value for color_max that shows the usual range of the mode.
+    pub fn default_max(self, settings: &SimulationSettings) -> f32 {
+        match self {
+            ColorMode::Species | ColorMode::Heading => 1.,
+            ColorMode::Speed => settings.max_velocity / 4.,
+            ColorMode::Neighbours => 30.,
+            ColorMode::Force => settings.max_distance as f32 * settings.force_factor * 5.,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Colormap {
+    #[default]
+    Viridis = 0,
+    Magma = 1,
+    Inferno = 2,
+    Plasma = 3,
+}
+
+/// What happens to particles at the bounds of the world.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BoundaryMode {
+    /// Particles leaving on one side enter on the opposite side and
+    /// feel forces across the bounds.
+    #[default]
+    Wrap = 0,
+    /// Particles bounce off the bounds elastically.
+    Reflect = 1,
+    /// Particles stop at the bounds.
+    Clamp = 2,
+    /// Particles are pushed back by a wall that repels like a particle
+    /// closer than min_distance.
+    SoftWall = 3,
+}
+
+/// A static obstacle inside of the world.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Obstacle {
+    pub shape: ObstacleShape,
+    pub response: ObstacleResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ObstacleShape {
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+    Rectangle {
+        center: Vec2,
+        size: Vec2,
+    },
+    /// Connected line segments, closing it makes a container
+    Polyline {
+        points: Vec<Vec2>,
+        thickness: f32,
+    },
+}
+
+/// What happens to particles that reach an obstacle.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObstacleResponse {
+    /// Particles bounce off elastically.
+    #[default]
+    Collide = 0,
+    /// Particles are pushed away like by the soft walls, but can be pushed through.
+    Repel = 1,
+}
+
+/// An acceleration of every particle, on top of the interactions between particles.
+/// Strengths are in world units per second squared.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ForceField {
+    Gravity {
+        acceleration: Vec2,
+    },
+    /// Pulls particles within the radius towards the center, or pushes them away
+    /// if the strength is negative. Strongest at the center.
+    Attractor {
+        center: Vec2,
+        strength: f32,
+        radius: f32,
+    },
+    /// Swirls particles within the radius around the center like the swirl brush,
+    /// the other way around if the strength is negative.
+    Vortex {
+        center: Vec2,
+        strength: f32,
+        radius: f32,
+    },
+    /// Currents without sources or sinks that change over time (curl noise).
+    /// `scale` is the size of the swirls in world units, `speed` how fast they change.
+    Noise {
+        strength: f32,
+        scale: f32,
+        speed: f32,
+    },
+}
+
+/// Physical parameters of the particles of one color, used instead of the global
+/// ones with per_species.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Species {
+    /// Inertia, the forces on the particles are divided by it.
+    /// Force fields are accelerations and move all species the same.
+    pub mass: f32,
+    pub velocity_half_life: f32,
+    /// The particles are slowed down to this speed
+    pub max_velocity: f32,
+    /// Factor of particle_size
+    pub size: f32,
+}
+
+impl Default for Species {
+    /// The global parameters of the default [`SimulationSettings`]
+    fn default() -> Self {
+        SimulationSettings::default().global_species()
+    }
+}
+
+/// A particle of color `color` that is within `radius` of a particle of color `catalyst`
+/// turns into color `product` with `probability` per second. Colors are indices into
+/// color_order, like in matrix. If several reactions can happen, the first one that
+/// happens wins. Catalysts are only found among the particles that are checked for
+/// attractions, so with a low max_attractions in crowded cells some are missed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Reaction {
+    pub color: u32,
+    pub catalyst: u32,
+    pub product: u32,
+    /// At most max_distance, since only the surrounding grid cells are searched
+    pub radius: f32,
+    pub probability: f32,
 }
 
 #[derive(Component, ShaderType, Default, Debug, Clone, Copy)]
@@ -67,6 +279,11 @@ pub fn random_colors(count: usize) -> Vec<Color> {
         .collect()
 }
 
+/// Seeds are kept in the u32 range so they can be edited in the ui without precision loss.
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen::<u32>() as u64
+}
+
 #[derive(Resource, Serialize, Deserialize, Debug, Clone)]
 pub struct SimulationSettings {
     pub particle_count: usize,
@@ -81,12 +298,49 @@ pub struct SimulationSettings {
     pub max_velocity: f32,
     pub velocity_half_life: f32,
     pub force_factor: f32,
+    /// Strength of the brownian motion, the standard deviation of the random
+    /// velocity change of a particle per axis and square root of a second
+    #[serde(default)]
+    pub temperature: f32,
     pub max_attractions: u32,
     pub acceleration_method: AccelerationMethod,
+    #[serde(default)]
+    pub boundary_mode: BoundaryMode,
+    /// Seed for all random decisions (attractions, positions, colors), so that
+    /// runs with the same settings are reproducible. Random if None.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Simulated seconds per frame. Uses the frame time if None.
+    #[serde(default)]
+    pub fixed_delta_time: Option<f32>,
 
     pub color_count: usize,
     pub color_order: Vec<ColorId>,
     pub matrix: Vec<Vec<f32>>,
+    /// Use min_radius and max_radius instead of min_distance and max_distance.
+    /// max_distance is then the largest max radius, so it still is the size of the grid cells.
+    #[serde(default)]
+    pub per_pair_radii: bool,
+    /// Same layout as matrix
+    #[serde(default)]
+    pub min_radius: Vec<Vec<f32>>,
+    /// Same layout as matrix
+    #[serde(default)]
+    pub max_radius: Vec<Vec<f32>>,
+    /// Use the parameters in species instead of the global ones.
+    /// The global max_velocity is not enforced, the one of species is.
+    #[serde(default)]
+    pub per_species: bool,
+    /// Same layout as color_order
+    #[serde(default)]
+    pub species: Vec<Species>,
+    /// Up to [`MAX_REACTIONS`], see [`Reaction`]
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    #[serde(default)]
+    pub force_fields: Vec<ForceField>,
 
     // visual settings
     pub particle_size: f32,
@@ -94,11 +348,43 @@ pub struct SimulationSettings {
     pub circle_corners: u32,
     pub rgb: bool,
     pub rgb_speed: f32,
+    /// Fade the previous frames instead of clearing them
+    #[serde(default)]
+    pub trails: bool,
+    /// How much of the trails is left after each frame
+    #[serde(default = "default_trail_decay")]
+    pub trail_decay: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    /// Render in hdr with a bloom pass, so bright areas glow
+    #[serde(default)]
+    pub bloom: bool,
+    #[serde(default = "default_bloom_intensity")]
+    pub bloom_intensity: f32,
+    #[serde(default)]
+    pub color_mode: ColorMode,
+    #[serde(default)]
+    pub colormap: Colormap,
+    /// The value at the top of the colormap
+    #[serde(default = "default_color_max")]
+    pub color_max: f32,
+}
+
+fn default_trail_decay() -> f32 {
+    SimulationSettings::default().trail_decay
+}
+
+fn default_bloom_intensity() -> f32 {
+    SimulationSettings::default().bloom_intensity
+}
+
+fn default_color_max() -> f32 {
+    SimulationSettings::default().color_max
 }
 
 impl Default for SimulationSettings {
     fn default() -> Self {
-        Self {
+        let mut settings = Self {
             particle_count: 9000,
             bounds: UVec2::new(3600, 2100),
             max_distance: 250,
@@ -106,43 +392,169 @@ impl Default for SimulationSettings {
             max_velocity: 1000.0,
             velocity_half_life: 0.043,
             force_factor: 1.,
+            temperature: 0.,
             max_attractions: 10_000,
             acceleration_method: AccelerationMethod::R1,
+            boundary_mode: BoundaryMode::Wrap,
+            seed: None,
+            fixed_delta_time: None,
 
             color_count: 4,
             color_order: (0..COLORS.len()).map(|i| ColorId::new(i as u32)).collect(),
             matrix: (0..COLORS.len())
                 .map(|_| (0..COLORS.len()).map(|_| 0.).collect())
                 .collect(),
+            per_pair_radii: false,
+            min_radius: vec![vec![50.; COLORS.len()]; COLORS.len()],
+            max_radius: vec![vec![250.; COLORS.len()]; COLORS.len()],
+            per_species: false,
+            // filled in from the global parameters below
+            species: Vec::new(),
+            reactions: Vec::new(),
+            obstacles: Vec::new(),
+            force_fields: Vec::new(),
 
             particle_size: 4.,
             shape: Shape::Circle,
             circle_corners: 16,
             rgb: false,
             rgb_speed: 1.,
-        }
+            trails: false,
+            trail_decay: 0.9,
+            blend_mode: BlendMode::Opaque,
+            bloom: false,
+            bloom_intensity: 0.3,
+            color_mode: ColorMode::Species,
+            colormap: Colormap::Viridis,
+            color_max: 1.,
+        };
+        settings.reset_species();
+        settings
     }
 }
 
 impl SimulationSettings {
+    /// Rng seeded from `seed`, or from entropy if there is no seed.
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Picks a new random seed if a seed is set.
+    pub fn reseed(&mut self) {
+        if let Some(seed) = &mut self.seed {
+            *seed = random_seed();
+        }
+    }
+
     pub fn randomize_colors(&mut self) {
-        self.color_order.shuffle(&mut rand::thread_rng());
+        let mut rng = self.rng();
+        self.color_order.shuffle(&mut rng);
     }
 
     pub fn randomize_attractions(&mut self) {
-        self.matrix = (0..COLORS.len())
-            .map(|_| {
-                (0..COLORS.len())
-                    .map(|_| rand::thread_rng().gen_range(-1.0..1.0))
-                    .collect()
-            })
+        let mut rng = self.rng();
+        let size = self.palette_size();
+        self.matrix = (0..size)
+            .map(|_| (0..size).map(|_| rng.gen_range(-1.0..1.0)).collect())
             .collect();
     }
 
     pub fn reset_attractions(&mut self) {
-        self.matrix = (0..COLORS.len())
-            .map(|_| (0..COLORS.len()).map(|_| 0.).collect())
-            .collect();
+        let size = self.palette_size();
+        self.matrix = (0..size).map(|_| (0..size).map(|_| 0.).collect()).collect();
+    }
+
+    /// Sets all radii to min_distance and max_distance.
+    pub fn reset_radii(&mut self) {
+        let size = self.palette_size();
+        self.min_radius = vec![vec![self.min_distance as f32; size]; size];
+        self.max_radius = vec![vec![self.max_distance as f32; size]; size];
+    }
+
+    /// Sets all species to the global parameters.
+    pub fn reset_species(&mut self) {
+        self.species = vec![self.global_species(); self.palette_size()];
+    }
+
+    /// The global parameters as a species, with a mass and size of 1.
+    pub fn global_species(&self) -> Species {
+        Species {
+            mass: 1.,
+            velocity_half_life: self.velocity_half_life,
+            max_velocity: self.max_velocity,
+            size: 1.,
+        }
+    }
+
+    /// The parameters of particles of color `color`. Without per_species
+    /// the velocity isn't limited.
+    pub fn species(&self, color: usize) -> Species {
+        if self.per_species {
+            self.species[color]
+        } else {
+            Species {
+                max_velocity: f32::MAX,
+                ..self.global_species()
+            }
+        }
+    }
+
+    /// Amount of colors that color_order and the matrices have entries for.
+    /// At least the size of [`COLORS`] and color_count, but it doesn't shrink when
+    /// color_count is lowered, so particles with a higher color keep their interactions.
+    pub fn palette_size(&self) -> usize {
+        self.color_order.len()
+    }
+
+    /// Grows color_order and the matrices to have an entry for every color.
+    /// Needs to be called after increasing color_count.
+    pub fn resize_colors(&mut self) {
+        let size = self
+            .color_count
+            .max(COLORS.len())
+            .max(self.color_order.len());
+
+        grow_matrix(&mut self.matrix, size, 0.);
+        grow_matrix(&mut self.min_radius, size, self.min_distance as f32);
+        grow_matrix(&mut self.max_radius, size, self.max_distance as f32);
+        if self.species.len() < size {
+            self.species.resize(size, self.global_species());
+        }
+
+        // adding missing colors to color_order
+        let colors = self.color_order.clone();
+        for i in 0..size as u32 {
+            if !colors.contains(&ColorId::new(i)) {
+                self.color_order.push(ColorId::new(i));
+            }
+        }
+    }
+
+    /// The min and max radius for a particle of color `color` interacting with a
+    /// particle of color `other`.
+    pub fn radii(&self, color: usize, other: usize) -> (f32, f32) {
+        if self.per_pair_radii {
+            (self.min_radius[other][color], self.max_radius[other][color])
+        } else {
+            (self.min_distance as f32, self.max_distance as f32)
+        }
+    }
+
+    /// With per pair radii the grid cells have to be as large as the largest max radius.
+    pub fn update_max_distance_from_radii(&mut self) {
+        if !self.per_pair_radii {
+            return;
+        }
+        let largest = self.max_radius[..self.color_count]
+            .iter()
+            .flat_map(|row| &row[..self.color_count])
+            .fold(1f32, |a, b| a.max(*b));
+        if largest.ceil() as u32 != self.max_distance {
+            self.update_max_distance(largest.ceil() as u32);
+        }
     }
 
     pub fn max_distance(&self) -> u32 {
@@ -150,7 +562,7 @@ impl SimulationSettings {
     }
 
     pub fn update_max_distance(&mut self, max_distance: u32) {
-        self.max_distance = max_distance;
+        self.max_distance = max_distance.max(1);
         self.update_bounds(self.bounds);
     }
 
@@ -159,10 +571,11 @@ impl SimulationSettings {
     }
 
     pub fn update_bounds(&mut self, bounds: UVec2) {
-        // round to closest multiple of max_distance
+        // round to closest multiple of max_distance, there has to be at least one cell
         self.bounds = ((bounds.as_vec2() / self.max_distance as f32)
             .round()
-            .as_uvec2())
+            .as_uvec2()
+            .max(UVec2::ONE))
             * self.max_distance;
     }
 
@@ -175,42 +588,339 @@ impl SimulationSettings {
         let mut settings = self.clone();
         let color_count = settings.color_count;
 
-        // removing unused rows and columns from matrix
-        settings.matrix.truncate(color_count);
-        for row in settings.matrix.iter_mut() {
-            row.truncate(color_count);
-        }
+        // removing unused rows and columns from matrices
+        truncate_matrix(&mut settings.matrix, color_count);
+        truncate_matrix(&mut settings.min_radius, color_count);
+        truncate_matrix(&mut settings.max_radius, color_count);
 
-        // removing unused colors in color_order
+        // removing unused colors in color_order and species
         settings.color_order.truncate(color_count);
+        settings.species.truncate(color_count);
 
         // let pretty = PrettyConfig::new()
         //     .depth_limit(2)
         //     .separate_tuple_members(true)
         //     .enumerate_arrays(true);
         // to_string_pretty(&settings, pretty).expect("Serialization failed")
-        serde_json::to_string(&settings).expect("Serialization failed")
+        let mut value = serde_json::to_value(&settings).expect("Serialization failed");
+        value["version"] = SETTINGS_VERSION.into();
+        value.to_string()
     }
 
-    pub fn deserialize(s: &str) -> Option<Self> {
-        let mut settings: Self = serde_json::from_str(s).ok()?;
+    /// Reads settings of the current or any older version.
+    /// The error describes why the settings were rejected.
+    pub fn deserialize(s: &str) -> Result<Self, String> {
+        let mut value: Value =
+            serde_json::from_str(s).map_err(|e| format!("The settings are not valid json: {e}"))?;
+        let object = value
+            .as_object_mut()
+            .ok_or("The settings are not a json object")?;
 
-        // adding missing rows and columns to matrix
-        for _ in settings.color_count..COLORS.len() {
-            settings.matrix.push(vec![0.; COLORS.len()]);
+        let version = match object.remove("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or("The settings version is not a number")?,
+            None => 0,
+        };
+        if version > SETTINGS_VERSION {
+            return Err(format!(
+                "The settings are from a newer version of the app \
+                 (settings version {version}, supported up to {SETTINGS_VERSION})"
+            ));
         }
-        for row in settings.matrix.iter_mut() {
-            row.resize_with(COLORS.len(), Default::default);
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(object);
         }
 
-        // adding missing colors to color_order
-        let colors = settings.color_order.clone();
-        for i in 0..COLORS.len() {
-            if !colors.contains(&ColorId::new(i as u32)) {
-                settings.color_order.push(ColorId::new(i as u32));
+        let mut settings: Self =
+            serde_json::from_value(value).map_err(|e| format!("The settings are invalid: {e}"))?;
+        settings
+            .validate_sizes()
+            .map_err(|e| format!("The settings are invalid: {e}"))?;
+
+        // adding missing rows and columns to matrices and missing colors to color_order
+        settings.resize_colors();
+        settings.update_max_distance_from_radii();
+        settings
+            .validate()
+            .map_err(|e| format!("The settings are invalid: {e}"))?;
+
+        Ok(settings)
+    }
+
+    /// Checks what serde can't, the error describes the first problem.
+    /// Checks the sizes that loading allocates for, before resize_colors grows the matrices.
+    fn validate_sizes(&self) -> Result<(), String> {
+        for (name, len) in [
+            ("color_count", self.color_count),
+            ("color_order", self.color_order.len()),
+            ("matrix", self.matrix.len()),
+            ("min_radius", self.min_radius.len()),
+            ("max_radius", self.max_radius.len()),
+            ("species", self.species.len()),
+        ] {
+            if len > MAX_PALETTE_SIZE {
+                return Err(format!(
+                    "{name} has {len} colors, at most {MAX_PALETTE_SIZE} are supported"
+                ));
+            }
+        }
+        if self.reactions.len() > MAX_REACTIONS {
+            return Err(format!(
+                "there are {} reactions, at most {MAX_REACTIONS} are supported",
+                self.reactions.len()
+            ));
+        }
+        // cell_count doubles the bounds
+        if self.bounds.max_element() > u32::MAX / 2 {
+            return Err(format!("the bounds {} are too large", self.bounds));
+        }
+        if self.max_distance > 0 {
+            let cells = self.bounds.as_u64vec2() * 2 / self.max_distance as u64;
+            if cells.x * cells.y > MAX_CELL_COUNT {
+                return Err(format!(
+                    "the bounds {} with max_distance {} need {} grid cells, at most {MAX_CELL_COUNT} are supported",
+                    self.bounds,
+                    self.max_distance,
+                    cells.x * cells.y
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.color_count == 0 {
+            return Err("there has to be at least one color".to_string());
+        }
+        // forces are divided by the mass
+        if let Some(color) = self.species.iter().position(|species| species.mass <= 0.) {
+            return Err(format!("the mass of species {color} has to be positive"));
+        }
+        // the gpu counts particles in u32
+        if self.particle_count > u32::MAX as usize {
+            return Err(format!(
+                "the particle count {} is too large",
+                self.particle_count
+            ));
+        }
+        if self.max_distance == 0 {
+            return Err("max_distance has to be positive".to_string());
+        }
+        if self.bounds.min_element() < self.max_distance {
+            return Err(format!(
+                "the bounds {} have to be at least max_distance {}",
+                self.bounds, self.max_distance
+            ));
+        }
+        for i in 0..self.color_count {
+            for j in 0..self.color_count {
+                let (min, max) = (self.min_radius[i][j], self.max_radius[i][j]);
+                if min > max {
+                    return Err(format!(
+                        "the min radius {min} of colors {i} and {j} is larger than the max radius {max}"
+                    ));
+                }
             }
         }
+        Ok(())
+    }
+}
 
-        Some(settings)
+fn truncate_matrix(matrix: &mut Vec<Vec<f32>>, size: usize) {
+    matrix.truncate(size);
+    for row in matrix.iter_mut() {
+        row.truncate(size);
+    }
+}
+
+fn grow_matrix(matrix: &mut Vec<Vec<f32>>, size: usize, value: f32) {
+    if matrix.len() < size {
+        matrix.resize_with(size, || vec![value; size]);
+    }
+    for row in matrix.iter_mut() {
+        if row.len() < size {
+            row.resize(size, value);
+        }
     }
 }
+
+#[test]
+fn test_more_colors_than_palette() {
+    let mut settings = SimulationSettings {
+        color_count: 40,
+        ..default()
+    };
+    settings.resize_colors();
+    settings.randomize_attractions();
+    assert_eq!(settings.palette_size(), 40);
+    assert_eq!(settings.matrix.len(), 40);
+    assert_eq!(settings.max_radius[39].len(), 40);
+    assert_ne!(palette_color(20), palette_color(21));
+
+    let loaded = SimulationSettings::deserialize(&settings.serialize()).unwrap();
+    assert_eq!(loaded.color_count, 40);
+    assert_eq!(loaded.matrix, settings.matrix);
+    assert_eq!(&loaded.color_order[..40], &settings.color_order[..40]);
+
+    // lowering the color count keeps the interactions of the higher colors
+    settings.color_count = 3;
+    settings.resize_colors();
+    assert_eq!(settings.palette_size(), 40);
+
+    // huge sizes are rejected before anything is allocated for them
+    let current: Value = serde_json::from_str(&settings.serialize()).unwrap();
+    let mut huge = current.clone();
+    huge["color_count"] = 1_000_000_000.into();
+    assert!(SimulationSettings::deserialize(&huge.to_string()).is_err());
+    let mut huge = current;
+    huge["bounds"] = serde_json::json!([1_000_000, 1_000_000]);
+    let error = SimulationSettings::deserialize(&huge.to_string()).unwrap_err();
+    assert!(error.contains("grid cells"));
+    huge["bounds"] = serde_json::json!([4_000_000_000u32, 4_000_000_000u32]);
+    assert!(SimulationSettings::deserialize(&huge.to_string()).is_err());
+}
+
+#[test]
+fn test_per_pair_radii_serialization() {
+    let mut settings = SimulationSettings {
+        per_pair_radii: true,
+        color_count: 2,
+        ..default()
+    };
+    settings.max_radius[1][0] = 400.;
+    settings.min_radius[0][1] = 20.;
+    settings.update_max_distance_from_radii();
+    assert_eq!(settings.max_distance(), 400);
+    assert_eq!(settings.radii(0, 1), (50., 400.));
+
+    let loaded = SimulationSettings::deserialize(&settings.serialize()).unwrap();
+    assert_eq!(loaded.max_radius[1][0], 400.);
+    assert_eq!(loaded.min_radius[0][1], 20.);
+    assert_eq!(loaded.max_distance(), 400);
+
+    settings.min_radius[1][1] = 300.;
+    assert!(SimulationSettings::deserialize(&settings.serialize()).is_err());
+    settings.min_radius[1][1] = 0.;
+
+    let mut invalid = settings.clone();
+    invalid.color_count = 0;
+    assert!(SimulationSettings::deserialize(&invalid.serialize()).is_err());
+    let mut invalid = settings.clone();
+    invalid.bounds = UVec2::new(0, 800);
+    assert!(SimulationSettings::deserialize(&invalid.serialize()).is_err());
+
+    // radii larger than the bounds still leave cells
+    settings.update_max_distance(10_000);
+    assert_eq!(settings.bounds(), UVec2::splat(10_000));
+    assert_eq!(settings.cell_count(), UVec2::splat(2));
+}
+
+#[test]
+fn test_seeded_attractions() {
+    let mut a = SimulationSettings {
+        seed: Some(42),
+        ..default()
+    };
+    let mut b = a.clone();
+    a.randomize_attractions();
+    b.randomize_attractions();
+    assert_eq!(a.matrix, b.matrix);
+
+    b.reseed();
+    assert_ne!(a.seed, b.seed);
+    b.randomize_attractions();
+    assert_ne!(a.matrix, b.matrix);
+}
+
+#[test]
+fn test_settings_versions() {
+    let settings = SimulationSettings {
+        color_count: 3,
+        boundary_mode: BoundaryMode::Reflect,
+        per_pair_radii: true,
+        temperature: 2.,
+        trails: true,
+        trail_decay: 0.5,
+        bloom_intensity: 0.7,
+        color_max: 3.,
+        ..default()
+    };
+    let current: Value = serde_json::from_str(&settings.serialize()).unwrap();
+    assert_eq!(current["version"], SETTINGS_VERSION);
+
+    // settings from before versioning
+    let mut old = current.clone();
+    let object = old.as_object_mut().unwrap();
+    for field in ["version", "boundary_mode", "per_pair_radii"] {
+        object.remove(field);
+    }
+    let loaded = SimulationSettings::deserialize(&old.to_string()).unwrap();
+    assert_eq!(loaded.boundary_mode, BoundaryMode::Wrap);
+    assert!(!loaded.per_pair_radii);
+    assert_eq!(loaded.radii(1, 2), (50., 250.));
+    assert_eq!(
+        loaded.matrix,
+        SimulationSettings::deserialize(&settings.serialize())
+            .unwrap()
+            .matrix
+    );
+
+    // only the fields from before versioning are required, newer ones have defaults
+    let required = [
+        "particle_count",
+        "bounds",
+        "max_distance",
+        "min_distance",
+        "max_velocity",
+        "velocity_half_life",
+        "force_factor",
+        "max_attractions",
+        "acceleration_method",
+        "color_count",
+        "color_order",
+        "matrix",
+        "particle_size",
+        "shape",
+        "circle_corners",
+        "rgb",
+        "rgb_speed",
+    ];
+    let defaults: Value = serde_json::from_str(
+        &SimulationSettings {
+            color_count: 3,
+            ..default()
+        }
+        .serialize(),
+    )
+    .unwrap();
+    for field in current.as_object().unwrap().keys() {
+        let mut missing = current.clone();
+        missing.as_object_mut().unwrap().remove(field);
+        let loaded = SimulationSettings::deserialize(&missing.to_string());
+        if required.contains(&field.as_str()) {
+            assert!(loaded.is_err(), "{field} is required");
+        } else {
+            let loaded: Value = serde_json::from_str(&loaded.unwrap().serialize()).unwrap();
+            assert_eq!(loaded[field], defaults[field], "default of {field}");
+        }
+    }
+
+    let mut newer = current.clone();
+    newer["version"] = (SETTINGS_VERSION + 1).into();
+    let error = SimulationSettings::deserialize(&newer.to_string()).unwrap_err();
+    assert!(error.contains("newer version"));
+
+    let mut invalid = current;
+    invalid["particle_count"] = "many".into();
+    assert!(SimulationSettings::deserialize(&invalid.to_string()).is_err());
+    assert!(SimulationSettings::deserialize("{").is_err());
+
+    let mut massless = settings;
+    massless.species[1].mass = 0.;
+    assert!(SimulationSettings::deserialize(&massless.serialize()).is_err());
+    assert_eq!(
+        Species::default(),
+        SimulationSettings::default().global_species()
+    );
+}
@@ -1,21 +1,32 @@
+use std::collections::HashMap;
+
 use bevy::{
-    core_pipeline::core_2d::graph::{Core2d, Node2d},
+    color::palettes::css::{DEEP_SKY_BLUE, ORANGE, WHITE},
+    core_pipeline::{
+        bloom::BloomSettings,
+        core_2d::graph::{Core2d, Node2d},
+        tonemapping::Tonemapping,
+    },
     ecs::query::QueryItem,
     prelude::*,
     render::{
+        extract_component::{ComponentUniforms, DynamicUniformIndex, UniformComponentPlugin},
         render_graph::{self, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNodeRunner},
-        render_resource::*,
-        renderer::RenderContext,
+        render_phase::TrackedRenderPass,
+        render_resource::{binding_types::uniform_buffer, *},
+        renderer::{RenderContext, RenderDevice},
         texture::BevyDefault,
         view::{ViewTarget, ViewUniformOffset},
-        RenderApp,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
 };
 
 use crate::{
-    camera::ParticleCamera,
+    camera::{ParticleCamera, ParticleSize},
     compute::{ParticleBindGroupLayouts, ParticleBindGroups, SHADER_DRAW},
-    data::{Shape, SimulationSettings},
+    data::{BlendMode, ForceField, ObstacleResponse, ObstacleShape, Shape, SimulationSettings},
+    heatmap::{Heatmap, HeatmapLabel, HeatmapNode, HeatmapPipeline},
+    trails::{prepare_trail_textures, TrailPipelines, TrailTextures, TRAIL_FORMAT},
 };
 
 pub struct DrawPlugin;
@@ -24,26 +35,79 @@ impl Plugin for DrawPlugin {
     fn build(&self, app: &mut App) {
         // We are borrowing the bind groups from the compute plugin.
         // So very little setup is needed here.
+        app.init_resource::<Heatmap>()
+            .add_plugins(UniformComponentPlugin::<ViewParticleSettings>::default())
+            .add_systems(
+                PostUpdate,
+                (update_bloom, draw_obstacles, draw_force_fields),
+            );
+
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .init_resource::<TrailTextures>()
+            .add_systems(ExtractSchedule, extract_view_particle_settings)
+            .add_systems(
+                Render,
+                (
+                    prepare_trail_textures.in_set(RenderSet::PrepareResources),
+                    prepare_view_bind_group.in_set(RenderSet::PrepareBindGroups),
+                ),
+            )
             .add_render_graph_node::<ViewNodeRunner<DrawParticleNode>>(Core2d, DrawParticleLabel)
-            .add_render_graph_edge(Core2d, Node2d::Tonemapping, DrawParticleLabel);
+            // before bloom and tonemapping, so particles can glow in hdr
+            .add_render_graph_edges(
+                Core2d,
+                (Node2d::EndMainPass, DrawParticleLabel, Node2d::Bloom),
+            )
+            .add_render_graph_node::<ViewNodeRunner<HeatmapNode>>(Core2d, HeatmapLabel)
+            .add_render_graph_edges(
+                Core2d,
+                (
+                    Node2d::Tonemapping,
+                    HeatmapLabel,
+                    Node2d::EndMainPassPostProcessing,
+                ),
+            );
     }
 
     fn finish(&self, app: &mut App) {
         let render_app = app.sub_app_mut(RenderApp);
         render_app.init_resource::<DrawParticlePipeline>();
+        render_app.init_resource::<TrailPipelines>();
+        render_app.init_resource::<HeatmapPipeline>();
     }
 }
 
 #[derive(Resource)]
 pub struct DrawParticlePipeline {
-    pipeline: CachedRenderPipelineId,
+    /// By target format (ldr, hdr and the trail texture) and blend mode
+    pipelines: HashMap<(TextureFormat, BlendMode), CachedRenderPipelineId>,
+    /// Layout of the ViewParticleSettings in group 1
+    view_layout: BindGroupLayout,
+}
+
+impl DrawParticlePipeline {
+    /// Whether the pipelines are compiled, before that DrawParticleNode draws nothing.
+    pub fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        self.pipelines.values().all(|&pipeline| {
+            matches!(
+                pipeline_cache.get_render_pipeline_state(pipeline),
+                CachedPipelineState::Ok(_)
+            )
+        })
+    }
 }
 
 impl FromWorld for DrawParticlePipeline {
     fn from_world(world: &mut World) -> Self {
         let layouts = world.resource::<ParticleBindGroupLayouts>();
+        let view_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            "view_particle_settings",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                uniform_buffer::<ViewParticleSettings>(true),
+            ),
+        );
 
         // Only loading it here instead of using the internal asset
         // because the internal asset doesn't hot reload (bug)
@@ -51,10 +115,10 @@ impl FromWorld for DrawParticlePipeline {
         let shader = SHADER_DRAW;
 
         let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline = pipeline_cache.queue_render_pipeline(
-            bevy::render::render_resource::RenderPipelineDescriptor {
+        let queue_pipeline = |format, blend_mode| {
+            pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
                 label: None,
-                layout: layouts.to_vec(),
+                layout: vec![layouts[0].clone(), view_layout.clone()],
                 push_constant_ranges: Vec::new(),
                 vertex: VertexState {
                     shader: shader.clone(),
@@ -67,8 +131,8 @@ impl FromWorld for DrawParticlePipeline {
                     shader_defs: vec![],
                     entry_point: "fragment".into(),
                     targets: vec![Some(ColorTargetState {
-                        format: TextureFormat::bevy_default(),
-                        blend: None,
+                        format,
+                        blend: blend_state(blend_mode),
                         write_mask: ColorWrites::ALL,
                     })],
                 }),
@@ -79,10 +143,170 @@ impl FromWorld for DrawParticlePipeline {
                     mask: !0,
                     alpha_to_coverage_enabled: false,
                 },
+            })
+        };
+
+        let mut pipelines = HashMap::new();
+        for format in [
+            TextureFormat::bevy_default(),
+            ViewTarget::TEXTURE_FORMAT_HDR,
+            TRAIL_FORMAT,
+        ] {
+            for blend_mode in [BlendMode::Opaque, BlendMode::Alpha, BlendMode::Additive] {
+                pipelines
+                    .entry((format, blend_mode))
+                    .or_insert_with(|| queue_pipeline(format, blend_mode));
+            }
+        }
+
+        DrawParticlePipeline {
+            pipelines,
+            view_layout,
+        }
+    }
+}
+
+/// What differs between the views the particles are drawn in
+#[derive(Component, ShaderType, Clone, Copy)]
+pub struct ViewParticleSettings {
+    pub particle_size: f32,
+}
+
+#[derive(Resource)]
+struct ViewParticleBindGroup(BindGroup);
+
+/// The particle size of each camera, settings.particle_size unless it has a ParticleSize.
+fn extract_view_particle_settings(
+    mut commands: Commands,
+    settings: Extract<Res<SimulationSettings>>,
+    cameras: Extract<Query<(Entity, &ParticleCamera, Option<&ParticleSize>)>>,
+) {
+    for (entity, _, size) in &cameras {
+        commands.get_or_spawn(entity).insert(ViewParticleSettings {
+            particle_size: size.map_or(settings.particle_size, |size| size.0),
+        });
+    }
+}
+
+fn prepare_view_bind_group(
+    mut commands: Commands,
+    pipeline: Res<DrawParticlePipeline>,
+    uniforms: Res<ComponentUniforms<ViewParticleSettings>>,
+    device: Res<RenderDevice>,
+) {
+    let Some(binding) = uniforms.binding() else {
+        commands.remove_resource::<ViewParticleBindGroup>();
+        return;
+    };
+    let bind_group = device.create_bind_group(
+        None,
+        &pipeline.view_layout,
+        &BindGroupEntries::single(binding),
+    );
+    commands.insert_resource(ViewParticleBindGroup(bind_group));
+}
+
+fn blend_state(blend_mode: BlendMode) -> Option<BlendState> {
+    match blend_mode {
+        BlendMode::Opaque => None,
+        BlendMode::Alpha => Some(BlendState::ALPHA_BLENDING),
+        BlendMode::Additive => Some(BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
             },
-        );
+            alpha: BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        }),
+    }
+}
+
+/// Outlines of the obstacles, white if particles collide with them and orange if they are repelled.
+fn draw_obstacles(settings: Res<SimulationSettings>, mut gizmos: Gizmos) {
+    for obstacle in &settings.obstacles {
+        let color = match obstacle.response {
+            ObstacleResponse::Collide => WHITE,
+            ObstacleResponse::Repel => ORANGE,
+        };
+        match &obstacle.shape {
+            ObstacleShape::Circle { center, radius } => {
+                gizmos.circle_2d(*center, *radius, color);
+            }
+            ObstacleShape::Rectangle { center, size } => {
+                gizmos.rect_2d(*center, 0., *size, color);
+            }
+            ObstacleShape::Polyline { points, thickness } => {
+                // the outline of a capsule around each segment
+                let radius = thickness / 2.;
+                for segment in points.windows(2) {
+                    let offset = (segment[1] - segment[0]).perp().normalize_or_zero() * radius;
+                    gizmos.line_2d(segment[0] + offset, segment[1] + offset, color);
+                    gizmos.line_2d(segment[0] - offset, segment[1] - offset, color);
+                }
+                for point in points {
+                    gizmos.circle_2d(*point, radius, color);
+                }
+            }
+        }
+    }
+}
 
-        DrawParticlePipeline { pipeline }
+/// The reach of attractors and vortices. Fields that act everywhere aren't drawn.
+fn draw_force_fields(settings: Res<SimulationSettings>, mut gizmos: Gizmos) {
+    for field in &settings.force_fields {
+        if let ForceField::Attractor { center, radius, .. }
+        | ForceField::Vortex { center, radius, .. } = field
+        {
+            gizmos.circle_2d(*center, *radius, DEEP_SKY_BLUE);
+            // marking the center, where the field is strongest
+            gizmos.circle_2d(*center, radius / 20., DEEP_SKY_BLUE);
+        }
+    }
+}
+
+/// Bloom needs hdr, and tonemapping to bring the bright parts back into range.
+fn update_bloom(
+    mut commands: Commands,
+    settings: Res<SimulationSettings>,
+    mut cameras: Query<
+        (
+            Entity,
+            &mut Camera,
+            &mut Tonemapping,
+            Option<&mut BloomSettings>,
+        ),
+        With<ParticleCamera>,
+    >,
+) {
+    for (entity, mut camera, mut tonemapping, bloom) in &mut cameras {
+        if camera.hdr != settings.bloom {
+            camera.hdr = settings.bloom;
+            *tonemapping = match settings.bloom {
+                true => Tonemapping::TonyMcMapface,
+                false => Tonemapping::None,
+            };
+        }
+        match (settings.bloom, bloom) {
+            (true, Some(mut bloom)) => {
+                if bloom.intensity != settings.bloom_intensity {
+                    bloom.intensity = settings.bloom_intensity;
+                }
+            }
+            (true, None) => {
+                commands.entity(entity).insert(BloomSettings {
+                    intensity: settings.bloom_intensity,
+                    ..BloomSettings::NATURAL
+                });
+            }
+            (false, Some(_)) => {
+                commands.entity(entity).remove::<BloomSettings>();
+            }
+            (false, None) => {}
+        }
     }
 }
 
@@ -97,54 +321,123 @@ impl render_graph::ViewNode for DrawParticleNode {
         &'static ViewTarget,
         &'static ParticleCamera,
         &'static ViewUniformOffset,
+        &'static DynamicUniformIndex<ViewParticleSettings>,
     );
 
     fn run(
         &self,
-        _graph: &mut RenderGraphContext,
+        graph: &mut RenderGraphContext,
         render_context: &mut RenderContext,
-        (view_target, _, uniform_offset): QueryItem<Self::ViewQuery>,
+        (view_target, _, uniform_offset, view_settings_index): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let bind_groups = world.resource::<ParticleBindGroups>();
+        let (Some(bind_groups), Some(view_bind_group)) = (
+            world.get_resource::<ParticleBindGroups>(),
+            world.get_resource::<ViewParticleBindGroup>(),
+        ) else {
+            return Ok(());
+        };
         let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline = world.resource::<DrawParticlePipeline>();
+        let pipelines = &world.resource::<DrawParticlePipeline>().pipelines;
+        let trail_pipelines = world.resource::<TrailPipelines>();
         let settings = world.resource::<SimulationSettings>();
 
-        let color_attachment = view_target.get_color_attachment();
-
-        let mut pass = render_context
-            .command_encoder()
-            .begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(color_attachment)],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-
-        if let CachedPipelineState::Ok(pipeline) =
-            pipeline_cache.get_render_pipeline_state(pipeline.pipeline)
-        {
-            let Pipeline::RenderPipeline(pipeline) = pipeline else {
-                unreachable!()
-            };
+        let particles = ParticleDraw {
+            bind_group: &bind_groups[0],
+            view_offset: uniform_offset.offset,
+            view_bind_group: &view_bind_group.0,
+            view_settings_offset: view_settings_index.index(),
+            settings,
+        };
 
-            pass.set_bind_group(0, &bind_groups[0], &[uniform_offset.offset]);
-            pass.set_pipeline(pipeline);
-            match settings.shape {
-                Shape::Circle => {
-                    pass.draw(
-                        0..settings.circle_corners * 3,
-                        0..settings.particle_count as u32,
-                    );
-                }
-                Shape::Square => {
-                    pass.draw(0..6, 0..settings.particle_count as u32);
-                }
+        // the falloff of glow is in the alpha, which opaque drawing ignores
+        let blend_mode = match (settings.shape, settings.blend_mode) {
+            (Shape::Glow, BlendMode::Opaque) => BlendMode::Additive,
+            (_, blend_mode) => blend_mode,
+        };
+        let format = view_target.main_texture_format();
+        let trail = world.resource::<TrailTextures>().get(&graph.view_entity());
+        let (Some(trail), Some(trail_pipeline), Some(fade), Some(composite)) = (
+            trail,
+            pipeline_cache.get_render_pipeline(pipelines[&(TRAIL_FORMAT, blend_mode)]),
+            pipeline_cache.get_render_pipeline(trail_pipelines.fade),
+            pipeline_cache.get_render_pipeline(trail_pipelines.composite[&format]),
+        ) else {
+            // without trails, the particles are drawn onto the view target directly
+            let mut pass = begin_pass(render_context, view_target.get_color_attachment());
+            let pipeline = pipelines[&(format, blend_mode)];
+            if let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline) {
+                particles.draw(&mut pass, pipeline);
             }
-        }
+            return Ok(());
+        };
+
+        // fading the previous frames and drawing this frame on top
+        let mut pass = begin_pass(
+            render_context,
+            RenderPassColorAttachment {
+                view: &trail.multisampled,
+                resolve_target: Some(&trail.resolved),
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            },
+        );
+        let decay = settings.trail_decay;
+        pass.set_blend_constant(LinearRgba::new(decay, decay, decay, decay));
+        pass.set_render_pipeline(fade);
+        pass.draw(0..3, 0..1);
+        particles.draw(&mut pass, trail_pipeline);
+        drop(pass);
+
+        let mut pass = begin_pass(render_context, view_target.get_color_attachment());
+        pass.set_render_pipeline(composite);
+        pass.set_bind_group(0, &trail.bind_group, &[]);
+        pass.draw(0..3, 0..1);
 
         Ok(())
     }
 }
+
+fn begin_pass<'a>(
+    render_context: &'a mut RenderContext,
+    color_attachment: RenderPassColorAttachment<'a>,
+) -> TrackedRenderPass<'a> {
+    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: None,
+        color_attachments: &[Some(color_attachment)],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+/// What's needed to draw the particles of one view
+struct ParticleDraw<'a> {
+    bind_group: &'a BindGroup,
+    view_offset: u32,
+    view_bind_group: &'a BindGroup,
+    view_settings_offset: u32,
+    settings: &'a SimulationSettings,
+}
+
+impl ParticleDraw<'_> {
+    fn draw<'a>(&'a self, pass: &mut TrackedRenderPass<'a>, pipeline: &'a RenderPipeline) {
+        let settings = self.settings;
+        pass.set_bind_group(0, self.bind_group, &[self.view_offset]);
+        pass.set_bind_group(1, self.view_bind_group, &[self.view_settings_offset]);
+        pass.set_render_pipeline(pipeline);
+        match settings.shape {
+            Shape::Circle => {
+                pass.draw(
+                    0..settings.circle_corners * 3,
+                    0..settings.particle_count as u32,
+                );
+            }
+            Shape::Square | Shape::Glow => {
+                pass.draw(0..6, 0..settings.particle_count as u32);
+            }
+        }
+    }
+}
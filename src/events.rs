@@ -1,7 +1,20 @@
 use bevy::prelude::*;
 
-#[derive(Event, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
 pub enum ParticleEvent {
     RandomizePositions,
     RandomizeColors,
+    /// Adds `count` particles of `color` at random positions inside of the circle.
+    /// settings.particle_count has to be increased by `count` in the same frame.
+    Spawn {
+        position: Vec2,
+        radius: f32,
+        color: u32,
+        count: u32,
+    },
+    /// Removes all particles inside of the circle.
+    Erase {
+        position: Vec2,
+        radius: f32,
+    },
 }
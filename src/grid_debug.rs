@@ -0,0 +1,298 @@
+//! Debug overlay of the acceleration grid. Draws the cell lines as derived from
+//! `cell_count()` and `max_distance` over the bounds, so rounding in `update_bounds` shows
+//! up as a last row or column that doesn't line up with the bounds. For the particle under
+//! the cursor it highlights the cells `update_velocity` iterates over. The particle is looked
+//! up on the gpu by `probe_particle`, only its cell and counter range are read back, without
+//! waiting for the gpu, whenever the cursor moves and a few times per second otherwise.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    color::palettes::css::{GRAY, LIME, ORANGE, YELLOW},
+    prelude::*,
+    render::{
+        render_resource::{
+            encase, Buffer, BufferDescriptor, BufferUsages, Maintain, MapMode, ShaderType,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+    window::PrimaryWindow,
+};
+use bevy_egui::EguiContexts;
+
+use crate::{
+    camera::ParticleCamera,
+    compute::{surrounding_cells, surrounding_cells_unwrapped, GpuBuffers, GpuProbe},
+    data::{BoundaryMode, SimulationSettings},
+};
+
+/// How often the particle under a cursor that doesn't move is looked up again
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct GridDebugPlugin;
+
+impl Plugin for GridDebugPlugin {
+    fn build(&self, app: &mut App) {
+        let grid_debug = GridDebug::default();
+        app.insert_resource(grid_debug.clone())
+            .add_systems(Update, draw_grid_debug);
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(grid_debug)
+            .init_resource::<ProbeReadback>()
+            .add_systems(Render, request_probe.in_set(RenderSet::Prepare))
+            .add_systems(Render, read_probe.in_set(RenderSet::Cleanup));
+    }
+}
+
+/// The particle closest to the cursor, as the gpu sees it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoveredParticle {
+    pub index: usize,
+    pub position: Vec2,
+    pub cell: UVec2,
+    pub cell_index: u32,
+    /// counter[cell_index]..counter[cell_index + 1], the range of sorted_indices
+    /// that holds the particles of the cell
+    pub range: (u32, u32),
+}
+
+impl HoveredParticle {
+    /// None if the probe found no particle or its cell doesn't match the cell count
+    pub fn from_probe(probe: &GpuProbe, cells: UVec2) -> Option<Self> {
+        // also covers NO_CELL
+        if probe.cell_index >= cells.x * cells.y {
+            return None;
+        }
+        Some(Self {
+            index: probe.index as usize,
+            position: probe.position,
+            cell: UVec2::new(probe.cell_index % cells.x, probe.cell_index / cells.x),
+            cell_index: probe.cell_index,
+            range: (probe.start, probe.end),
+        })
+    }
+}
+
+/// Reads back the result of probe_particle without waiting for the gpu.
+/// ParticleNode only runs probe_particle while a readback is requested.
+#[derive(Resource, Default)]
+pub struct ProbeReadback {
+    /// Receives a copy of the probe buffer, set while a readback is in flight
+    pub staging: Option<Buffer>,
+    /// Set by ParticleNode once the probe is copied into staging
+    pub copied: AtomicBool,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+impl ProbeReadback {
+    /// Whether ParticleNode should run probe_particle and copy it into staging
+    pub fn requested(&self) -> bool {
+        self.staging.is_some() && !self.map_requested && !self.copied.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GridDebugState {
+    pub enabled: bool,
+    /// The world space position of the cursor, None if it is over the ui or outside the window
+    cursor: Option<Vec2>,
+    pub hovered: Option<HoveredParticle>,
+}
+
+/// Written by the ui and the render world, the same state is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+pub struct GridDebug(Arc<Mutex<GridDebugState>>);
+
+impl GridDebug {
+    pub fn lock(&self) -> std::sync::MutexGuard<'_, GridDebugState> {
+        self.0.lock().unwrap()
+    }
+}
+
+fn draw_grid_debug(
+    grid_debug: Res<GridDebug>,
+    settings: Res<SimulationSettings>,
+    camera: Query<(&Camera, &GlobalTransform), With<ParticleCamera>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut egui_contexts: EguiContexts,
+    mut gizmos: Gizmos,
+) {
+    let mut state = grid_debug.lock();
+    if !state.enabled {
+        state.hovered = None;
+        return;
+    }
+
+    let egui_context = egui_contexts.ctx_mut();
+    let block_mouse = egui_context.is_pointer_over_area() || egui_context.is_using_pointer();
+    // posters spawn a second particle camera, the window camera is the one that is active
+    let camera = camera.iter().find(|(camera, _)| camera.order == 0);
+    state.cursor = match (block_mouse, camera, window.get_single()) {
+        (false, Some((camera, transform)), Ok(window)) => window
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world_2d(transform, cursor)),
+        _ => None,
+    };
+
+    let bounds = settings.bounds().as_vec2();
+    let max_distance = settings.max_distance() as f32;
+    let cells = settings.cell_count();
+    for x in 0..=cells.x {
+        let x = -bounds.x + x as f32 * max_distance;
+        gizmos.line_2d(Vec2::new(x, -bounds.y), Vec2::new(x, bounds.y), GRAY);
+    }
+    for y in 0..=cells.y {
+        let y = -bounds.y + y as f32 * max_distance;
+        gizmos.line_2d(Vec2::new(-bounds.x, y), Vec2::new(bounds.x, y), GRAY);
+    }
+    gizmos.rect_2d(Vec2::ZERO, 0., 2. * bounds, YELLOW);
+
+    let Some(hovered) = state.hovered else {
+        return;
+    };
+    let surrounding = match settings.boundary_mode {
+        BoundaryMode::Wrap => surrounding_cells(hovered.cell, cells).map(Some),
+        _ => surrounding_cells_unwrapped(hovered.cell, cells),
+    };
+    for cell in surrounding.into_iter().flatten() {
+        let color = match cell == hovered.cell_index {
+            true => LIME,
+            false => ORANGE,
+        };
+        let cell = UVec2::new(cell % cells.x, cell / cells.x);
+        let center = -bounds + (cell.as_vec2() + 0.5) * max_distance;
+        gizmos.rect_2d(center, 0., Vec2::splat(max_distance * 0.96), color);
+    }
+    gizmos.circle_2d(hovered.position, settings.particle_size * 2., LIME);
+}
+
+/// Writes the cursor into the probe buffer and requests a readback of probe_particle
+/// when the cursor moved or the last one is older than PROBE_INTERVAL.
+fn request_probe(
+    grid_debug: Res<GridDebug>,
+    buffers: Res<GpuBuffers>,
+    mut readback: ResMut<ProbeReadback>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut last_probe: Local<Option<(Vec2, Instant)>>,
+) {
+    let cursor = {
+        let mut state = grid_debug.lock();
+        let Some(cursor) = state.cursor.filter(|_| state.enabled) else {
+            state.hovered = None;
+            *last_probe = None;
+            return;
+        };
+        cursor
+    };
+    // one readback at a time
+    if readback.staging.is_some() {
+        return;
+    }
+    if let Some((last_cursor, time)) = *last_probe {
+        if last_cursor == cursor && time.elapsed() < PROBE_INTERVAL {
+            return;
+        }
+    }
+    let Some(probe) = buffers.probe.buffer() else {
+        return;
+    };
+    *last_probe = Some((cursor, Instant::now()));
+
+    // cursor is the first field of GpuProbe
+    let bytes = [cursor.x.to_le_bytes(), cursor.y.to_le_bytes()].concat();
+    queue.write_buffer(probe, 0, &bytes);
+    readback.staging = Some(device.create_buffer(&BufferDescriptor {
+        label: Some("probe readback"),
+        size: GpuProbe::min_size().get(),
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    }));
+}
+
+/// Maps the probe once ParticleNode copied it and sets the hovered particle when it is mapped.
+fn read_probe(
+    grid_debug: Res<GridDebug>,
+    settings: Option<Res<SimulationSettings>>,
+    mut readback: ResMut<ProbeReadback>,
+    device: Res<RenderDevice>,
+) {
+    let Some(staging) = readback.staging.clone() else {
+        return;
+    };
+    if readback.copied.swap(false, Ordering::Relaxed) {
+        let mapped = readback.mapped.clone();
+        device.map_buffer(
+            &staging.slice(..),
+            MapMode::Read,
+            move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Relaxed),
+                Err(e) => error!("Failed to map probe readback buffer: {e}"),
+            },
+        );
+        readback.map_requested = true;
+    } else if !readback.map_requested {
+        // ParticleNode didn't get to probe, e.g. because the pipelines weren't ready yet
+        *readback = ProbeReadback::default();
+        return;
+    }
+
+    device.poll(Maintain::Poll);
+    if !readback.mapped.load(Ordering::Relaxed) {
+        return;
+    }
+    let bytes = staging.slice(..).get_mapped_range();
+    let probe = encase::StorageBuffer::new(&bytes[..]).create::<GpuProbe>();
+    drop(bytes);
+    staging.unmap();
+    *readback = ProbeReadback::default();
+
+    let probe = match probe {
+        Ok(probe) => probe,
+        Err(e) => return error!("Failed to read the probe: {e}"),
+    };
+    let mut state = grid_debug.lock();
+    if state.enabled {
+        state.hovered = settings.and_then(|s| HoveredParticle::from_probe(&probe, s.cell_count()));
+    }
+}
+
+#[test]
+fn test_from_probe() {
+    // 4x2 cells
+    let cells = UVec2::new(4, 2);
+    let probe = GpuProbe {
+        cursor: Vec2::new(125., 55.),
+        position: Vec2::new(120., 60.),
+        index: 2,
+        cell_index: 7,
+        start: 1,
+        end: 3,
+    };
+
+    let hovered = HoveredParticle::from_probe(&probe, cells).unwrap();
+    assert_eq!(hovered.index, 2);
+    assert_eq!(hovered.position, Vec2::new(120., 60.));
+    assert_eq!(hovered.cell, UVec2::new(3, 1));
+    assert_eq!(hovered.cell_index, 7);
+    assert_eq!(hovered.range, (1, 3));
+
+    // no particle within max_distance of the cursor
+    let no_cell = GpuProbe {
+        cell_index: u32::MAX,
+        ..probe
+    };
+    assert!(HoveredParticle::from_probe(&no_cell, cells).is_none());
+    // the cell count changed since probing
+    assert!(HoveredParticle::from_probe(&probe, UVec2::new(2, 2)).is_none());
+}
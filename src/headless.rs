@@ -0,0 +1,151 @@
+//! Batch mode that runs the simulation on the cpu without opening a window.
+//!
+//! Usage: `bevy_particle_life headless (--settings <file> | --snapshot <file>) --steps <n> --output <file> [--delta-time <seconds>]`
+//!
+//! The time step defaults to the fixed time step of the settings or 1/60 seconds.
+//! The output is written as a snapshot if it has the `plsnap` extension and as csv otherwise.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{cpu::CpuSimulation, data::SimulationSettings, snapshot::Snapshot};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadlessArgs {
+    pub settings: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub steps: u32,
+    pub output: PathBuf,
+    pub delta_time: Option<f32>,
+}
+
+impl HeadlessArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut settings = None;
+        let mut snapshot = None;
+        let mut steps = None;
+        let mut output = None;
+        let mut delta_time = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--settings" => settings = Some(PathBuf::from(value()?)),
+                "--snapshot" => snapshot = Some(PathBuf::from(value()?)),
+                "--steps" => {
+                    steps = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --steps: {e}"))?,
+                    )
+                }
+                "--output" => output = Some(PathBuf::from(value()?)),
+                "--delta-time" => {
+                    delta_time = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --delta-time: {e}"))?,
+                    )
+                }
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+
+        if settings.is_none() && snapshot.is_none() {
+            return Err("missing --settings or --snapshot".to_string());
+        }
+
+        Ok(Self {
+            settings,
+            snapshot,
+            steps: steps.ok_or("missing --steps")?,
+            output: output.ok_or("missing --output")?,
+            delta_time,
+        })
+    }
+}
+
+pub fn run(args: &HeadlessArgs) -> Result<(), String> {
+    let (settings, mut simulation) = match (&args.snapshot, &args.settings) {
+        (Some(path), _) => {
+            let snapshot = Snapshot::load(path)
+                .map_err(|e| format!("failed to load {}: {e}", path.display()))?;
+            (snapshot.settings, CpuSimulation::new(snapshot.particles))
+        }
+        (None, Some(path)) => {
+            let settings = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            let settings = SimulationSettings::deserialize(&settings)
+                .map_err(|e| format!("invalid settings in {}: {e}", path.display()))?;
+            let simulation = CpuSimulation::from_settings(&settings, &mut settings.rng());
+            (settings, simulation)
+        }
+        (None, None) => unreachable!("checked in HeadlessArgs::parse"),
+    };
+
+    let delta_time = args
+        .delta_time
+        .or(settings.fixed_delta_time)
+        .unwrap_or(1. / 60.);
+    for _ in 0..args.steps {
+        simulation.step(&settings, delta_time);
+    }
+
+    let result = if args.output.extension().is_some_and(|e| e == "plsnap") {
+        Snapshot::new(&settings, simulation.particles.clone()).save(&args.output)
+    } else {
+        write_particles(&simulation, &args.output)
+    };
+    result.map_err(|e| format!("failed to write {}: {e}", args.output.display()))?;
+    println!(
+        "Wrote {} particles after {} steps to {}",
+        simulation.particles.len(),
+        args.steps,
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+/// Writes the particles as csv, one particle per line.
+fn write_particles(simulation: &CpuSimulation, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "x,y,velocity_x,velocity_y,color")?;
+    for p in &simulation.particles {
+        writeln!(
+            file,
+            "{},{},{},{},{}",
+            p.position.x, p.position.y, p.velocity.x, p.velocity.y, p.color.id
+        )?;
+    }
+    file.flush()
+}
+
+#[test]
+fn test_parse_headless_args() {
+    let args: Vec<String> = [
+        "--settings",
+        "world.json",
+        "--steps",
+        "100",
+        "--output",
+        "out.csv",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let parsed = HeadlessArgs::parse(&args).unwrap();
+    assert_eq!(parsed.steps, 100);
+    assert_eq!(parsed.settings, Some(PathBuf::from("world.json")));
+    assert_eq!(parsed.delta_time, None);
+
+    assert!(HeadlessArgs::parse(&args[..4]).is_err());
+    assert!(HeadlessArgs::parse(&["--steps".to_string()]).is_err());
+}
@@ -0,0 +1,135 @@
+//! Density heatmap. Shows the particle count of each grid cell over the world, which is
+//! what update_velocity iterates over, so it shows where clustering makes the simulation slow.
+//! The counts come from the counter buffer of the last step, nothing extra is computed.
+
+use std::collections::HashMap;
+
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        render_graph::{self, RenderGraphContext, RenderLabel, ViewNode},
+        render_resource::*,
+        renderer::RenderContext,
+        texture::BevyDefault,
+        view::{ViewTarget, ViewUniformOffset},
+    },
+};
+
+use crate::{
+    camera::ParticleCamera,
+    compute::{GpuSettings, ParticleBindGroupLayouts, ParticleBindGroups, SHADER_HEATMAP},
+};
+
+#[derive(Resource, Debug, Clone)]
+pub struct Heatmap {
+    pub enabled: bool,
+    pub opacity: f32,
+    /// Particle count at the top of the colormap
+    pub max_count: u32,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            opacity: 0.6,
+            max_count: 50,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct HeatmapPipeline {
+    /// By the format of the view target, which is different in hdr
+    pipelines: HashMap<TextureFormat, CachedRenderPipelineId>,
+}
+
+impl FromWorld for HeatmapPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layouts = world.resource::<ParticleBindGroupLayouts>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let pipelines = [
+            TextureFormat::bevy_default(),
+            ViewTarget::TEXTURE_FORMAT_HDR,
+        ]
+        .into_iter()
+        .map(|format| {
+            let pipeline = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("heatmap".into()),
+                layout: layouts.to_vec(),
+                push_constant_ranges: Vec::new(),
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader: SHADER_HEATMAP,
+                    shader_defs: vec![],
+                    entry_point: "fragment".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+            });
+            (format, pipeline)
+        })
+        .collect();
+
+        Self { pipelines }
+    }
+}
+
+#[derive(RenderLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeatmapLabel;
+
+/// Draws the heatmap after tonemapping, so it isn't affected by bloom.
+#[derive(Default)]
+pub struct HeatmapNode;
+
+impl ViewNode for HeatmapNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ParticleCamera,
+        &'static ViewUniformOffset,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, _, uniform_offset): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if world.resource::<GpuSettings>().heatmap_opacity <= 0. {
+            return Ok(());
+        }
+        let Some(bind_groups) = world.get_resource::<ParticleBindGroups>() else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline =
+            world.resource::<HeatmapPipeline>().pipelines[&view_target.main_texture_format()];
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline) else {
+            return Ok(());
+        };
+
+        // the main texture is already resolved at this point, so this draws on it without msaa
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("heatmap"),
+            color_attachments: &[Some(view_target.get_unsampled_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_render_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_groups[0], &[uniform_offset.offset]);
+        pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
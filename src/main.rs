@@ -1,46 +1,138 @@
-use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
+use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*, window::WindowMode};
 use bevy_egui::EguiPlugin;
 
+use args::Args;
+use brush::{update_brush, Brush};
 use camera::{camera_controls, CameraSettings, ParticleCamera};
+use capture::{capture, Capture};
 use compute::ComputePlugin;
-use data::SimulationSettings;
 use draw::DrawPlugin;
 use events::ParticleEvent;
+use grid_debug::GridDebugPlugin;
+use poster::PosterPlugin;
+use presets::Presets;
+use snapshot::SnapshotPlugin;
+use ui::UiState;
 
+mod args;
+mod brush;
 mod camera;
+mod capture;
 mod compute;
+mod cpu;
 mod data;
 mod draw;
 mod events;
+mod grid_debug;
+mod headless;
+mod heatmap;
+mod poster;
+mod presets;
+mod snapshot;
+mod trails;
 mod ui;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("headless") {
+        let result =
+            headless::HeadlessArgs::parse(&args[1..]).and_then(|args| headless::run(&args));
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("render") {
+        let result = poster::PosterArgs::parse(&args[1..]).and_then(|args| poster::run(&args));
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let args = match Args::parse(&args) {
+        Ok(args) if args.help => {
+            println!("{}", args::USAGE);
+            return;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", args::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let settings = match args.initial_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut window = Window::default();
+    if let Some(size) = args.window_size {
+        window.resolution = size.into();
+    }
+    if args.fullscreen {
+        window.mode = WindowMode::BorderlessFullscreen;
+    }
+
     App::new()
         .add_event::<ParticleEvent>()
+        .init_resource::<Brush>()
+        .init_resource::<Presets>()
+        .init_resource::<Capture>()
+        .init_resource::<UiState>()
+        .insert_resource(settings)
+        .insert_resource(args)
         .add_plugins((
-            DefaultPlugins,
+            DefaultPlugins.set(WindowPlugin {
+                primary_window: Some(window),
+                ..default()
+            }),
             EguiPlugin,
             // Used by ui to display the fps.
             FrameTimeDiagnosticsPlugin::default(),
             ComputePlugin,
             DrawPlugin,
+            SnapshotPlugin,
+            PosterPlugin,
+            GridDebugPlugin,
         ))
         .add_systems(Startup, setup)
-        .add_systems(Update, (ui::ui, camera_controls).chain())
+        .add_systems(
+            Update,
+            (
+                capture,
+                ui::toggle_ui,
+                ui::ui,
+                update_brush,
+                camera_controls,
+            )
+                .chain(),
+        )
         .run();
 }
 
-fn setup(mut commands: Commands) {
+fn setup(mut commands: Commands, args: Res<Args>, mut ui_state: ResMut<UiState>) {
+    ui_state.hidden = args.hide_ui;
+
+    let mut camera = Camera2dBundle::default();
+    if let Some(position) = args.camera {
+        camera.transform.translation = position.extend(camera.transform.translation.z);
+    }
+    if let Some(zoom) = args.zoom {
+        camera.projection.scale = 1. / zoom;
+    }
+
     commands.spawn((
-        Camera2dBundle::default(),
+        camera,
         CameraSettings {
             pan_speed: 1.,
             scroll_speed: 1.,
         },
         ParticleCamera,
     ));
-
-    let mut settings = SimulationSettings::default();
-    settings.randomize_attractions();
-    commands.insert_resource(settings);
 }
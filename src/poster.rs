@@ -0,0 +1,488 @@
+//! Rendering the world into an offscreen image of any resolution, for example for posters.
+//!
+//! A poster is rendered by an additional [`ParticleCamera`] that targets an image, so it
+//! uses the same DrawParticleNode as the window. It works from the running app and
+//! without a window:
+//!
+//! `bevy_particle_life render (--settings <file> | --snapshot <file>) --size <WxH> --output <file>
+//! [--steps <n>] [--delta-time <seconds>] [--particle-size <size>]`
+//!
+//! Without a window, the camera covers the whole bounds.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_resource::{
+            BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+            ImageDataLayout, PipelineCache, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::{BevyDefault, GpuImage},
+        Render, RenderApp, RenderSet,
+    },
+    window::{ExitCondition, PrimaryWindow},
+    winit::WinitPlugin,
+};
+
+use crate::{
+    brush::Brush,
+    camera::{CameraSettings, ParticleCamera, ParticleSize},
+    compute::{ComputePlugin, GpuBuffers},
+    data::SimulationSettings,
+    draw::{DrawParticlePipeline, DrawPlugin},
+    events::ParticleEvent,
+    snapshot::{map_staging_buffer, Snapshot, SnapshotPlugin, SnapshotQueue},
+};
+
+pub struct PosterPlugin;
+
+impl Plugin for PosterPlugin {
+    fn build(&self, app: &mut App) {
+        let results = PosterResults::default();
+        app.init_resource::<PosterQueue>()
+            .insert_resource(results.clone())
+            .add_plugins(ExtractComponentPlugin::<PosterCamera>::default())
+            .add_systems(Last, update_posters);
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(results)
+            .add_systems(Render, save_posters.in_set(RenderSet::Cleanup));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Poster {
+    pub size: UVec2,
+    pub output: PathBuf,
+    /// Particle size while the poster is rendered, the current particle size if None
+    pub particle_size: Option<f32>,
+    /// Show the whole bounds instead of what the window shows
+    pub fit_bounds: bool,
+    /// Simulation steps to run before the poster is saved
+    pub steps: u32,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct PosterQueue {
+    pending: VecDeque<Poster>,
+    next_id: u32,
+    /// Amount of posters that were saved or failed
+    pub finished: usize,
+    /// The error of the last poster that failed
+    pub error: Option<String>,
+}
+
+impl PosterQueue {
+    pub fn request(&mut self, poster: Poster) {
+        self.pending.push_back(poster);
+    }
+
+    /// Amount of posters waiting to be rendered
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Saved posters, written in the render world and read in the main world.
+/// The same resource is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+struct PosterResults(Arc<Mutex<Vec<PosterResult>>>);
+
+/// Id of the poster and where it was saved
+type PosterResult = (u32, Result<PathBuf, String>);
+
+#[derive(Component, ExtractComponent, Debug, Clone)]
+pub struct PosterCamera {
+    id: u32,
+    output: PathBuf,
+    steps: u32,
+    image: Handle<Image>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_posters(
+    mut commands: Commands,
+    mut queue: ResMut<PosterQueue>,
+    results: Res<PosterResults>,
+    posters: Query<(Entity, &PosterCamera)>,
+    // the camera of the window, posters don't have camera settings
+    camera: Query<(&Transform, &OrthographicProjection), With<CameraSettings>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    settings: Res<SimulationSettings>,
+    mut images: ResMut<Assets<Image>>,
+    device: Res<RenderDevice>,
+) {
+    for (id, result) in std::mem::take(&mut *results.0.lock().unwrap()) {
+        match result {
+            Ok(path) => info!("Saved poster to {}", path.display()),
+            Err(e) => {
+                error!("{e}");
+                queue.error = Some(e);
+            }
+        }
+        queue.finished += 1;
+
+        for (entity, poster) in &posters {
+            if poster.id == id {
+                commands.entity(entity).despawn();
+                images.remove(&poster.image);
+            }
+        }
+    }
+
+    if !posters.is_empty() {
+        return;
+    }
+    let Some(poster) = queue.pending.pop_front() else {
+        return;
+    };
+
+    let max_size = device.limits().max_texture_dimension_2d;
+    if poster.size.min_element() == 0 || poster.size.max_element() > max_size {
+        let e = format!(
+            "Poster size {}x{} is not supported, the maximum is {max_size}x{max_size}",
+            poster.size.x, poster.size.y
+        );
+        error!("{e}");
+        queue.error = Some(e);
+        queue.finished += 1;
+        return;
+    }
+
+    let size = Extent3d {
+        width: poster.size.x,
+        height: poster.size.y,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("poster"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::bevy_default(),
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    let image = images.add(image);
+
+    let mut bundle = Camera2dBundle::default();
+    bundle.camera.target = RenderTarget::Image(image.clone());
+    bundle.camera.order = -1;
+    match (camera.get_single(), window.get_single()) {
+        // the same part of the world as the window, at the resolution of the poster
+        (Ok((transform, projection)), Ok(window)) if !poster.fit_bounds => {
+            bundle.transform = *transform;
+            bundle.projection.scale = projection.scale * window.width() / poster.size.x as f32;
+        }
+        _ => {
+            let bounds = 2. * settings.bounds().as_vec2();
+            bundle.projection.scale = (bounds / poster.size.as_vec2()).max_element();
+        }
+    }
+
+    let id = queue.next_id;
+    queue.next_id += 1;
+    let mut camera = commands.spawn((
+        bundle,
+        ParticleCamera,
+        PosterCamera {
+            id,
+            output: poster.output,
+            steps: poster.steps,
+            image,
+        },
+    ));
+    if let Some(particle_size) = poster.particle_size {
+        camera.insert(ParticleSize(particle_size));
+    }
+}
+
+/// Saves the posters once they were drawn after the requested simulation steps.
+#[allow(clippy::too_many_arguments)]
+fn save_posters(
+    posters: Query<&PosterCamera>,
+    images: Res<RenderAssets<GpuImage>>,
+    buffers: Res<GpuBuffers>,
+    draw_pipeline: Res<DrawParticlePipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    results: Res<PosterResults>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    // step when the poster was first seen and frames it was drawn in, None once it is saved
+    mut progress: Local<HashMap<u32, Option<(u32, u32)>>>,
+) {
+    progress.retain(|id, _| posters.iter().any(|p| p.id == *id));
+
+    for poster in &posters {
+        let entry = progress
+            .entry(poster.id)
+            .or_insert(Some((buffers.steps(), 0)));
+        let Some((start_step, drawn_frames)) = entry else {
+            continue;
+        };
+        if draw_pipeline.is_ready(&pipeline_cache) {
+            *drawn_frames += 1;
+        }
+        // the pipeline could have become ready after drawing in this frame
+        if *drawn_frames < 2 || buffers.steps() - *start_step < poster.steps {
+            continue;
+        }
+        let Some(image) = images.get(&poster.image) else {
+            continue;
+        };
+
+        let result = save_image(image, &poster.output, &device, &queue)
+            .map(|_| poster.output.clone())
+            .map_err(|e| format!("Failed to save poster to {}: {e}", poster.output.display()));
+        results.0.lock().unwrap().push((poster.id, result));
+        *entry = None;
+    }
+}
+
+/// Copies the image from the gpu and saves it, the format is taken from the extension.
+fn save_image(
+    image: &GpuImage,
+    path: &Path,
+    device: &RenderDevice,
+    queue: &RenderQueue,
+) -> Result<(), String> {
+    let size = Extent3d {
+        width: image.size.x,
+        height: image.size.y,
+        depth_or_array_layers: 1,
+    };
+    let bytes_per_pixel = image.texture_format.block_copy_size(None).unwrap_or(4) as usize;
+    let row_bytes = image.size.x as usize * bytes_per_pixel;
+    // rows of the copy have to be aligned
+    let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
+
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("poster_readback"),
+        size: (padded_row_bytes * image.size.y as usize) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut command_encoder =
+        device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    command_encoder.copy_texture_to_buffer(
+        image.texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &staging,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes as u32),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit([command_encoder.finish()]);
+
+    let padded = map_staging_buffer(&staging, device);
+    let data = padded
+        .chunks_exact(padded_row_bytes)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect();
+
+    Image::new(
+        size,
+        TextureDimension::D2,
+        data,
+        image.texture_format,
+        RenderAssetUsages::default(),
+    )
+    .try_into_dynamic()
+    .map_err(|e| e.to_string())?
+    .save(path)
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PosterArgs {
+    pub settings: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub poster: Poster,
+    pub delta_time: Option<f32>,
+}
+
+impl PosterArgs {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut settings = None;
+        let mut snapshot = None;
+        let mut size = None;
+        let mut output = None;
+        let mut steps = 0;
+        let mut particle_size = None;
+        let mut delta_time = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--settings" => settings = Some(PathBuf::from(value()?)),
+                "--snapshot" => snapshot = Some(PathBuf::from(value()?)),
+                "--size" => {
+                    let (x, y) = value()?
+                        .split_once('x')
+                        .ok_or("invalid --size, expected e.g. 7680x4320")?;
+                    let parse = |s: &str| s.parse().map_err(|e| format!("invalid --size: {e}"));
+                    size = Some(UVec2::new(parse(x)?, parse(y)?));
+                }
+                "--output" => output = Some(PathBuf::from(value()?)),
+                "--steps" => {
+                    steps = value()?
+                        .parse()
+                        .map_err(|e| format!("invalid --steps: {e}"))?
+                }
+                "--particle-size" => {
+                    particle_size = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --particle-size: {e}"))?,
+                    )
+                }
+                "--delta-time" => {
+                    delta_time = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("invalid --delta-time: {e}"))?,
+                    )
+                }
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+
+        if settings.is_none() && snapshot.is_none() {
+            return Err("missing --settings or --snapshot".to_string());
+        }
+
+        Ok(Self {
+            settings,
+            snapshot,
+            poster: Poster {
+                size: size.ok_or("missing --size")?,
+                output: output.ok_or("missing --output")?,
+                particle_size,
+                fit_bounds: true,
+                steps,
+            },
+            delta_time,
+        })
+    }
+}
+
+/// Renders a single poster without opening a window.
+pub fn run(args: &PosterArgs) -> Result<(), String> {
+    let (mut settings, particles) = match (&args.snapshot, &args.settings) {
+        (Some(path), _) => {
+            let snapshot = Snapshot::load(path)
+                .map_err(|e| format!("failed to load {}: {e}", path.display()))?;
+            (snapshot.settings, Some(snapshot.particles))
+        }
+        (None, Some(path)) => {
+            let settings = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            let settings = SimulationSettings::deserialize(&settings)
+                .map_err(|e| format!("invalid settings in {}: {e}", path.display()))?;
+            (settings, None)
+        }
+        (None, None) => unreachable!("checked in PosterArgs::parse"),
+    };
+    settings.fixed_delta_time = Some(
+        args.delta_time
+            .or(settings.fixed_delta_time)
+            .unwrap_or(1. / 60.),
+    );
+
+    let mut app = App::new();
+    app.add_event::<ParticleEvent>()
+        .init_resource::<Brush>()
+        .insert_resource(settings)
+        .add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+            ComputePlugin,
+            DrawPlugin,
+            SnapshotPlugin,
+            PosterPlugin,
+        ))
+        .add_systems(Update, exit_when_saved);
+
+    if let Some(particles) = particles {
+        app.world()
+            .resource::<SnapshotQueue>()
+            .request_load(particles);
+    }
+    app.world_mut()
+        .resource_mut::<PosterQueue>()
+        .request(args.poster.clone());
+
+    match app.run() {
+        AppExit::Success => Ok(()),
+        AppExit::Error(_) => Err("failed to render the poster".to_string()),
+    }
+}
+
+fn exit_when_saved(queue: Res<PosterQueue>, mut exit: EventWriter<AppExit>) {
+    if queue.finished > 0 {
+        exit.send(match queue.error {
+            Some(_) => AppExit::error(),
+            None => AppExit::Success,
+        });
+    }
+}
+
+#[test]
+fn test_parse_poster_args() {
+    let args: Vec<String> = [
+        "--settings",
+        "world.json",
+        "--size",
+        "7680x4320",
+        "--output",
+        "poster.png",
+        "--particle-size",
+        "8",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let parsed = PosterArgs::parse(&args).unwrap();
+    assert_eq!(parsed.poster.size, UVec2::new(7680, 4320));
+    assert_eq!(parsed.poster.particle_size, Some(8.));
+    assert_eq!(parsed.poster.steps, 0);
+    assert!(parsed.poster.fit_bounds);
+
+    assert!(PosterArgs::parse(&args[..4]).is_err());
+    assert!(PosterArgs::parse(&["--size".to_string(), "big".to_string()]).is_err());
+}
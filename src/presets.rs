@@ -0,0 +1,176 @@
+//! Named settings stored on disk, one json file per preset, plus presets built into the binary.
+//!
+//! User presets live in `<config dir>/particle_life/presets`, where the config dir is
+//! `%APPDATA%` on windows, `~/Library/Application Support` on macos and
+//! `$XDG_CONFIG_HOME` or `~/.config` elsewhere.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::data::SimulationSettings;
+
+pub const BUILTIN_PRESETS: &[(&str, &str)] = &[
+    ("Cells", include_str!("../assets/presets/cells.json")),
+    ("Swirls", include_str!("../assets/presets/swirls.json")),
+    (
+        "Walled garden",
+        include_str!("../assets/presets/walled_garden.json"),
+    ),
+];
+
+#[derive(Resource, Debug, Clone)]
+pub struct Presets {
+    /// None if no config directory could be determined, then only built-in presets are available
+    dir: Option<PathBuf>,
+    /// Names of the user presets, sorted
+    pub names: Vec<String>,
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self::new(config_dir().map(|dir| dir.join("particle_life").join("presets")))
+    }
+}
+
+impl Presets {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        let mut presets = Self {
+            dir,
+            names: Vec::new(),
+        };
+        presets.refresh();
+        presets
+    }
+
+    /// Reads the names of the user presets from disk.
+    pub fn refresh(&mut self) {
+        self.names = self
+            .dir
+            .as_deref()
+            .and_then(|dir| fs::read_dir(dir).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                match path.extension()? == "json" {
+                    true => Some(path.file_stem()?.to_str()?.to_string()),
+                    false => None,
+                }
+            })
+            .collect();
+        self.names.sort();
+    }
+
+    pub fn load(&self, name: &str) -> Result<SimulationSettings, String> {
+        let json = fs::read_to_string(self.path(name)?)
+            .map_err(|e| format!("Failed to read preset {name}: {e}"))?;
+        SimulationSettings::deserialize(&json).map_err(|e| format!("Preset {name}: {e}"))
+    }
+
+    pub fn load_builtin(name: &str) -> Result<SimulationSettings, String> {
+        let (_, json) = BUILTIN_PRESETS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| format!("There is no built-in preset {name}"))?;
+        SimulationSettings::deserialize(json).map_err(|e| format!("Preset {name}: {e}"))
+    }
+
+    /// Saves the settings under the name, replacing a preset with the same name.
+    pub fn save(&mut self, name: &str, settings: &SimulationSettings) -> Result<(), String> {
+        let path = self.path(name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        }
+        fs::write(&path, settings.serialize())
+            .map_err(|e| format!("Failed to save preset {name}: {e}"))?;
+        self.refresh();
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        let new_path = self.path(new_name)?;
+        if new_path.exists() {
+            return Err(format!("A preset named {new_name} already exists"));
+        }
+        fs::rename(self.path(name)?, new_path)
+            .map_err(|e| format!("Failed to rename preset {name}: {e}"))?;
+        self.refresh();
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        fs::remove_file(self.path(name)?)
+            .map_err(|e| format!("Failed to delete preset {name}: {e}"))?;
+        self.refresh();
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, String> {
+        let dir = self
+            .dir
+            .as_deref()
+            .ok_or("No config directory to store presets in")?;
+        if name.trim().is_empty() {
+            return Err("The preset name is empty".to_string());
+        }
+        if name.contains(['/', '\\', ':']) || Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(format!("{name} can't be used as a preset name"));
+        }
+        Ok(dir.join(format!("{name}.json")))
+    }
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    }
+}
+
+#[test]
+fn test_builtin_presets() {
+    for (name, _) in BUILTIN_PRESETS {
+        Presets::load_builtin(name).unwrap();
+    }
+}
+
+#[test]
+fn test_user_presets() {
+    let dir = std::env::temp_dir().join(format!("particle_life_presets_{}", std::process::id()));
+    let mut presets = Presets::new(Some(dir.clone()));
+    assert!(presets.names.is_empty());
+
+    let mut settings = SimulationSettings::default();
+    settings.particle_count = 1234;
+    presets.save("world", &settings).unwrap();
+    assert_eq!(presets.names, ["world"]);
+    assert_eq!(presets.load("world").unwrap().particle_count, 1234);
+
+    presets.save("another", &settings).unwrap();
+    assert!(presets.rename("world", "another").is_err());
+    presets.rename("world", "renamed").unwrap();
+    assert_eq!(presets.names, ["another", "renamed"]);
+
+    assert!(presets.save("../escape", &settings).is_err());
+    assert!(presets.save(" ", &settings).is_err());
+
+    presets.delete("another").unwrap();
+    presets.delete("renamed").unwrap();
+    assert!(presets.names.is_empty());
+    assert!(presets.load("renamed").is_err());
+
+    fs::remove_dir_all(dir).unwrap();
+}
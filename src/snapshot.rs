@@ -0,0 +1,352 @@
+//! Saving and restoring the full particle state.
+//!
+//! A snapshot file consists of a header followed by the raw particles:
+//! - 8 bytes magic `PLSNAPSH`
+//! - u32 version
+//! - u32 length of the settings json, followed by the settings json as written by
+//!   [`SimulationSettings::serialize`]
+//! - u64 particle count
+//! - the particles, 24 bytes each, in the same layout as the gpu particle buffer
+//!
+//! All numbers are little endian.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Maintain, MapMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    compute::GpuBuffers,
+    data::{ColorId, Particle, SimulationSettings},
+};
+
+const MAGIC: &[u8; 8] = b"PLSNAPSH";
+const VERSION: u32 = 1;
+pub const PARTICLE_SIZE: usize = 24;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        let queue = SnapshotQueue::default();
+        app.insert_resource(queue.clone());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(queue)
+            .add_systems(Render, save_snapshot.in_set(RenderSet::Cleanup));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub settings: SimulationSettings,
+    pub particles: Vec<Particle>,
+}
+
+impl Snapshot {
+    pub fn new(settings: &SimulationSettings, particles: Vec<Particle>) -> Self {
+        let mut settings = settings.clone();
+        settings.particle_count = particles.len();
+        Self {
+            settings,
+            particles,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read(&mut BufReader::new(file), len)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let settings = self.settings.serialize();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(settings.len() as u32).to_le_bytes())?;
+        writer.write_all(settings.as_bytes())?;
+        writer.write_all(&(self.particles.len() as u64).to_le_bytes())?;
+        writer.write_all(&particles_to_bytes(&self.particles))
+    }
+
+    /// Reads a snapshot of `len` bytes. The lengths in the header have to fit into it,
+    /// so a corrupt header can't cause a huge allocation.
+    pub fn read(reader: &mut impl Read, len: u64) -> io::Result<Self> {
+        let mut remaining = len.saturating_sub(MAGIC.len() as u64 + 8);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a particle snapshot"));
+        }
+
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {version}, expected {VERSION}"
+            )));
+        }
+
+        let settings_len = read_u32(reader)? as u64;
+        let settings = read_bytes(reader, settings_len, remaining, "settings")?;
+        remaining = remaining.saturating_sub(settings_len + 8);
+        let settings = std::str::from_utf8(&settings)
+            .map_err(|e| e.to_string())
+            .and_then(SimulationSettings::deserialize)
+            .map_err(|e| invalid_data(format!("invalid settings in snapshot: {e}")))?;
+
+        let mut count = [0; 8];
+        reader.read_exact(&mut count)?;
+        let particles_len = u64::from_le_bytes(count)
+            .checked_mul(PARTICLE_SIZE as u64)
+            .ok_or_else(|| invalid_data("the particle count is too large"))?;
+        let particles = read_bytes(reader, particles_len, remaining, "particles")?;
+        let particles = particles_from_bytes(&particles);
+        validate_particles(&particles, &settings).map_err(invalid_data)?;
+
+        Ok(Self::new(&settings, particles))
+    }
+}
+
+/// Reads exactly `len` bytes, which have to fit into the `remaining` bytes of the file.
+fn read_bytes(reader: &mut impl Read, len: u64, remaining: u64, what: &str) -> io::Result<Vec<u8>> {
+    if len > remaining {
+        return Err(invalid_data(format!("the {what} are longer than the file")));
+    }
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid_data(format!("the {what} are truncated")));
+    }
+    Ok(bytes)
+}
+
+/// The simulation indexes the matrices by color and the grid by position,
+/// so both have to be in range.
+fn validate_particles(particles: &[Particle], settings: &SimulationSettings) -> Result<(), String> {
+    let bounds = settings.bounds().as_vec2();
+    for (i, p) in particles.iter().enumerate() {
+        if p.color.id as usize >= settings.palette_size() {
+            return Err(format!("particle {i} has the unknown color {}", p.color.id));
+        }
+        if !p.position.is_finite() || p.position.abs().cmpgt(bounds).any() {
+            return Err(format!("particle {i} is outside of the bounds"));
+        }
+        if !p.velocity.is_finite() {
+            return Err(format!("particle {i} has an invalid velocity"));
+        }
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid_data(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+pub fn particles_to_bytes(particles: &[Particle]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(particles.len() * PARTICLE_SIZE);
+    for p in particles {
+        bytes.extend_from_slice(&p.position.x.to_le_bytes());
+        bytes.extend_from_slice(&p.position.y.to_le_bytes());
+        bytes.extend_from_slice(&p.velocity.x.to_le_bytes());
+        bytes.extend_from_slice(&p.velocity.y.to_le_bytes());
+        bytes.extend_from_slice(&p.color.id.to_le_bytes());
+        bytes.extend_from_slice(&p.padding.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn particles_from_bytes(bytes: &[u8]) -> Vec<Particle> {
+    bytes
+        .chunks_exact(PARTICLE_SIZE)
+        .map(|p| {
+            let word = |i: usize| [p[4 * i], p[4 * i + 1], p[4 * i + 2], p[4 * i + 3]];
+            let float = |i: usize| f32::from_le_bytes(word(i));
+            Particle {
+                position: Vec2::new(float(0), float(1)),
+                velocity: Vec2::new(float(2), float(3)),
+                color: ColorId::new(u32::from_le_bytes(word(4))),
+                padding: u32::from_le_bytes(word(5)),
+            }
+        })
+        .collect()
+}
+
+/// Requests from the main world that are handled in the render world.
+/// The same queue is inserted into both worlds.
+#[derive(Resource, Clone, Default)]
+pub struct SnapshotQueue {
+    save: Arc<Mutex<Option<PathBuf>>>,
+    load: Arc<Mutex<Option<Vec<Particle>>>>,
+}
+
+impl SnapshotQueue {
+    /// The particles are read back from the gpu and saved at the end of the next frame.
+    pub fn request_save(&self, path: PathBuf) {
+        *self.save.lock().unwrap() = Some(path);
+    }
+
+    /// The settings of the snapshot have to be applied to [`SimulationSettings`]
+    /// in the same frame, so the particle count matches.
+    pub fn request_load(&self, particles: Vec<Particle>) {
+        *self.load.lock().unwrap() = Some(particles);
+    }
+
+    pub fn take_load(&self) -> Option<Vec<Particle>> {
+        self.load.lock().unwrap().take()
+    }
+}
+
+fn save_snapshot(
+    queue: Res<SnapshotQueue>,
+    settings: Option<Res<SimulationSettings>>,
+    buffers: Res<GpuBuffers>,
+    device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(settings) = settings else {
+        return;
+    };
+    let Some(path) = queue.save.lock().unwrap().take() else {
+        return;
+    };
+
+    let particles = read_particles(&buffers, settings.particle_count, &device, &render_queue);
+    match Snapshot::new(&settings, particles).save(&path) {
+        Ok(()) => info!("Saved snapshot to {}", path.display()),
+        Err(e) => error!("Failed to save snapshot to {}: {e}", path.display()),
+    }
+}
+
+/// Copies the first `count` particles from the gpu, blocking until they are available.
+pub fn read_particles(
+    buffers: &GpuBuffers,
+    count: usize,
+    device: &RenderDevice,
+    queue: &RenderQueue,
+) -> Vec<Particle> {
+    let Some(particles) = buffers.particles.buffer() else {
+        return Vec::new();
+    };
+    let size = (count * PARTICLE_SIZE) as u64;
+    if size == 0 {
+        return Vec::new();
+    }
+    particles_from_bytes(&read_buffer(particles, size, device, queue))
+}
+
+/// Copies the first `size` bytes of the buffer from the gpu, blocking until they are available.
+/// The buffer needs the COPY_SRC usage.
+pub fn read_buffer(
+    buffer: &Buffer,
+    size: u64,
+    device: &RenderDevice,
+    queue: &RenderQueue,
+) -> Vec<u8> {
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("readback"),
+        size,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut command_encoder =
+        device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    command_encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit([command_encoder.finish()]);
+
+    map_staging_buffer(&staging, device)
+}
+
+/// Reads the whole buffer once the submitted copies into it are done.
+/// The buffer needs the MAP_READ usage.
+pub fn map_staging_buffer(staging: &Buffer, device: &RenderDevice) -> Vec<u8> {
+    let slice = staging.slice(..);
+    device.map_buffer(&slice, MapMode::Read, |result| {
+        if let Err(e) = result {
+            error!("Failed to map readback buffer: {e}");
+        }
+    });
+    device.poll(Maintain::Wait);
+
+    let bytes = slice.get_mapped_range().to_vec();
+    staging.unmap();
+    bytes
+}
+
+#[test]
+fn test_snapshot_roundtrip() {
+    let mut settings = SimulationSettings::default();
+    settings.randomize_attractions();
+    let particles = vec![
+        Particle {
+            position: Vec2::new(1., -2.),
+            velocity: Vec2::new(0.5, 3.),
+            color: ColorId::new(3),
+            padding: 0,
+        },
+        Particle {
+            position: Vec2::new(-100., 20.),
+            velocity: Vec2::ZERO,
+            color: ColorId::new(1),
+            padding: 0,
+        },
+    ];
+    let snapshot = Snapshot::new(&settings, particles.clone());
+
+    let mut bytes = Vec::new();
+    snapshot.write(&mut bytes).unwrap();
+    let read = |bytes: &[u8]| Snapshot::read(&mut &bytes[..], bytes.len() as u64);
+    let loaded = read(&bytes).unwrap();
+
+    assert_eq!(loaded.settings.particle_count, 2);
+    assert_eq!(loaded.settings.serialize(), snapshot.settings.serialize());
+    for (a, b) in loaded.particles.iter().zip(&particles) {
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.velocity, b.velocity);
+        assert_eq!(a.color, b.color);
+    }
+
+    // truncated
+    assert!(read(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Snapshot::read(&mut &bytes[..bytes.len() - 1], bytes.len() as u64).is_err());
+    // particle count far beyond the file
+    let count_start = bytes.len() - 2 * PARTICLE_SIZE - 8;
+    let mut corrupt = bytes.clone();
+    corrupt[count_start..count_start + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(read(&corrupt).is_err());
+    corrupt[count_start..count_start + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    assert!(read(&corrupt).is_err());
+    // color outside of the palette
+    let mut corrupt = bytes.clone();
+    let color_start = bytes.len() - PARTICLE_SIZE + 16;
+    corrupt[color_start..color_start + 4].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(read(&corrupt).is_err());
+
+    bytes[0] = b'X';
+    assert!(read(&bytes).is_err());
+}
@@ -0,0 +1,203 @@
+//! Motion trails. With trails enabled, particles are drawn into a texture per view that is
+//! faded by `trail_decay` each frame instead of being cleared, so moving particles leave
+//! streaks that show the flow. The texture is then drawn over the view target.
+
+use std::collections::HashMap;
+
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_resource::{
+            binding_types::{sampler, texture_2d},
+            *,
+        },
+        renderer::RenderDevice,
+        texture::BevyDefault,
+        view::ViewTarget,
+    },
+};
+
+use crate::{camera::ParticleCamera, compute::SHADER_TRAILS, data::SimulationSettings};
+
+/// Float, so faded trails don't get stuck at the smallest non zero value
+pub const TRAIL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Resource)]
+pub struct TrailPipelines {
+    pub fade: CachedRenderPipelineId,
+    /// By the format of the view target, which is different in hdr
+    pub composite: HashMap<TextureFormat, CachedRenderPipelineId>,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+impl FromWorld for TrailPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(
+            "trails",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                ),
+            ),
+        );
+        let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+
+        let multisample = MultisampleState {
+            count: 4,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+        // trails *= blend constant
+        let fade_blend = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::Constant,
+            operation: BlendOperation::Add,
+        };
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let fade = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("fade_trails".into()),
+            layout: vec![],
+            push_constant_ranges: Vec::new(),
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: SHADER_TRAILS,
+                shader_defs: vec![],
+                entry_point: "fade".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TRAIL_FORMAT,
+                    blend: Some(BlendState {
+                        color: fade_blend,
+                        alpha: fade_blend,
+                    }),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample,
+        });
+        let queue_composite = |format| {
+            pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("composite_trails".into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: Vec::new(),
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader: SHADER_TRAILS,
+                    shader_defs: vec![],
+                    entry_point: "composite".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format,
+                        blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample,
+            })
+        };
+        let composite = [
+            TextureFormat::bevy_default(),
+            ViewTarget::TEXTURE_FORMAT_HDR,
+        ]
+        .into_iter()
+        .map(|format| (format, queue_composite(format)))
+        .collect();
+
+        Self {
+            fade,
+            composite,
+            layout,
+            sampler,
+        }
+    }
+}
+
+/// The trails of one view
+pub struct Trail {
+    size: UVec2,
+    /// Particles are drawn into this with msaa, it keeps the trails between frames
+    pub multisampled: TextureView,
+    /// Where `multisampled` is resolved to, so it can be sampled
+    pub resolved: TextureView,
+    /// Samples `resolved` for the composite pass
+    pub bind_group: BindGroup,
+}
+
+/// Trails of the particle cameras, by view entity.
+/// Kept across frames, unlike the render world entities.
+#[derive(Resource, Default, Deref)]
+pub struct TrailTextures(HashMap<Entity, Trail>);
+
+/// Creates the trail textures of new or resized views and drops them when trails are turned off.
+pub fn prepare_trail_textures(
+    mut trails: ResMut<TrailTextures>,
+    views: Query<(Entity, &ExtractedCamera), With<ParticleCamera>>,
+    settings: Res<SimulationSettings>,
+    pipelines: Res<TrailPipelines>,
+    render_device: Res<RenderDevice>,
+) {
+    if !settings.trails {
+        trails.0.clear();
+        return;
+    }
+    trails.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, camera) in &views {
+        let Some(size) = camera.physical_target_size else {
+            continue;
+        };
+        if trails.get(&entity).is_some_and(|trail| trail.size == size) {
+            continue;
+        }
+
+        // new textures are zeroed, so the trails start out empty
+        let texture = |label, sample_count, usage| {
+            render_device
+                .create_texture(&TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: TextureDimension::D2,
+                    format: TRAIL_FORMAT,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        };
+        let multisampled = texture("trails_multisampled", 4, TextureUsages::RENDER_ATTACHMENT);
+        let resolved = texture(
+            "trails",
+            1,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        );
+        let bind_group = render_device.create_bind_group(
+            "trails",
+            &pipelines.layout,
+            &BindGroupEntries::sequential((&resolved, &pipelines.sampler)),
+        );
+
+        trails.0.insert(
+            entity,
+            Trail {
+                size,
+                multisampled,
+                resolved,
+                bind_group,
+            },
+        );
+    }
+}
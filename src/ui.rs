@@ -9,24 +9,90 @@ use bevy_egui::{
 };
 
 use crate::{
+    brush::{Brush, BrushMode},
     camera::CameraSettings,
-    data::{AccelerationMethod, Shape, SimulationSettings, COLORS},
+    capture::{timestamp, Capture, RECORD_KEY, SCREENSHOT_KEY},
+    data::{
+        palette_color, random_seed, AccelerationMethod, BlendMode, BoundaryMode, ColorMode,
+        Colormap, ForceField, Obstacle, ObstacleResponse, ObstacleShape, Reaction, Shape,
+        SimulationSettings, MAX_COLOR_COUNT, MAX_REACTIONS,
+    },
     events::ParticleEvent,
+    grid_debug::GridDebug,
+    heatmap::Heatmap,
+    poster::{Poster, PosterQueue},
+    presets::{Presets, BUILTIN_PRESETS},
+    snapshot::{Snapshot, SnapshotQueue},
 };
 
+/// Which matrix is shown in the matrix editor
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixView {
+    #[default]
+    Attraction,
+    MinRadius,
+    MaxRadius,
+}
+
+/// Shows and hides the settings window
+pub const TOGGLE_UI_KEY: KeyCode = KeyCode::F1;
+
+/// Ui state that isn't part of any setting
+#[derive(Resource)]
+pub struct UiState {
+    /// The settings window isn't drawn, see TOGGLE_UI_KEY
+    pub hidden: bool,
+    snapshot_path: String,
+    preset_name: String,
+    matrix_view: MatrixView,
+    poster_size: UVec2,
+    poster_fit_bounds: bool,
+    /// Particle size of posters, the current particle size if None
+    poster_particle_size: Option<f32>,
+    /// Why the last paste, load or preset change failed
+    error: Option<String>,
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            hidden: false,
+            snapshot_path: "snapshot.plsnap".to_string(),
+            preset_name: String::new(),
+            matrix_view: MatrixView::Attraction,
+            poster_size: UVec2::new(7680, 4320),
+            poster_fit_bounds: false,
+            poster_particle_size: None,
+            error: None,
+        }
+    }
+}
+
 pub fn ui(
     mut contexts: EguiContexts,
     mut settings: ResMut<SimulationSettings>,
-    mut camera_settings: Query<&mut CameraSettings>,
+    mut camera_settings: Query<(&mut CameraSettings, &Transform)>,
     diagnostic: Res<DiagnosticsStore>,
     mut time: ResMut<Time<Virtual>>,
     mut event_writer: EventWriter<ParticleEvent>,
     mut clipboard: ResMut<EguiClipboard>,
     mut window: Query<&mut Window>,
+    snapshots: Res<SnapshotQueue>,
+    mut brush: ResMut<Brush>,
+    mut presets: ResMut<Presets>,
+    mut capture: ResMut<Capture>,
+    mut posters: ResMut<PosterQueue>,
+    mut heatmap: ResMut<Heatmap>,
+    grid_debug: Res<GridDebug>,
+    mut state: ResMut<UiState>,
 ) {
     let mut window = window.single_mut();
 
+    // captured frames don't show the window
+    let capturing = capture.hides_ui();
+    let mut open = !state.hidden && !capturing;
     egui::Window::new("Settings")
+        .open(&mut open)
         .scroll([false, true])
         .default_height(650.)
         .default_width(260.)
@@ -63,6 +129,34 @@ pub fn ui(
                 time.set_relative_speed(relative_speed);
             }
 
+            ui.horizontal(|ui| {
+                let mut fixed = settings.fixed_delta_time.is_some();
+                ui.checkbox(&mut fixed, "fixed time step");
+                match (fixed, &mut settings.fixed_delta_time) {
+                    (true, Some(delta_time)) => {
+                        ui.add(
+                            egui::DragValue::new(delta_time)
+                                .speed(0.0001)
+                                .range(0.0001..=1.),
+                        );
+                    }
+                    (true, None) => settings.fixed_delta_time = Some(1. / 60.),
+                    (false, _) => settings.fixed_delta_time = None,
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let mut seeded = settings.seed.is_some();
+                ui.checkbox(&mut seeded, "seed");
+                match (seeded, &mut settings.seed) {
+                    (true, Some(seed)) => {
+                        ui.add(egui::DragValue::new(seed));
+                    }
+                    (true, None) => settings.seed = Some(random_seed()),
+                    (false, _) => settings.seed = None,
+                }
+            });
+
             ui.add(
                 egui::Slider::new(&mut settings.particle_count, 0..=300_000)
                     .text("particle count")
@@ -75,7 +169,8 @@ pub fn ui(
                     .clamp_to_range(false),
             );
             let mut max_distance = settings.max_distance();
-            ui.add(
+            ui.add_enabled(
+                !settings.per_pair_radii,
                 egui::Slider::new(&mut max_distance, 100..=1000)
                     .text("max distance")
                     .clamp_to_range(false),
@@ -95,6 +190,11 @@ pub fn ui(
                     .text("force scale")
                     .clamp_to_range(false),
             );
+            ui.add(
+                egui::Slider::new(&mut settings.temperature, 0.0..=500.0)
+                    .text("temperature")
+                    .clamp_to_range(false),
+            );
             let mut bounds = settings.bounds();
             ui.add(
                 egui::Slider::new(&mut bounds.x, 100..=30_000)
@@ -115,10 +215,30 @@ pub fn ui(
             );
 
             ui.add(
-                egui::Slider::new(&mut settings.color_count, 1..=COLORS.len())
+                egui::Slider::new(&mut settings.color_count, 1..=MAX_COLOR_COUNT)
                     .text("color count")
                     .clamp_to_range(false),
             );
+            settings.color_count = settings.color_count.max(1);
+            settings.resize_colors();
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.per_pair_radii, "per pair radii");
+                if settings.per_pair_radii && ui.button("Reset radii").clicked() {
+                    settings.reset_radii();
+                }
+            });
+            if settings.per_pair_radii {
+                ui.horizontal(|ui| {
+                    let view = &mut state.matrix_view;
+                    ui.selectable_value(view, MatrixView::Attraction, "Attraction");
+                    ui.selectable_value(view, MatrixView::MinRadius, "Min radius");
+                    ui.selectable_value(view, MatrixView::MaxRadius, "Max radius");
+                });
+            } else {
+                state.matrix_view = MatrixView::Attraction;
+            }
+            let view = state.matrix_view;
 
             if settings.color_count < 11 {
                 ui.add_space(10.);
@@ -137,20 +257,35 @@ pub fn ui(
                     ui.vertical(|ui| {
                         color_ui(ui, Srgba::NONE);
                         for color in 0..settings.color_count {
-                            color_ui(ui, COLORS[settings.color_order[color].id as usize]);
+                            color_ui(ui, palette_color(settings.color_order[color].id));
                         }
                     });
 
+                    // larger radii would leave less than two grid cells
+                    let largest_radius = settings.bounds().min_element() as f32;
                     for i in 0..settings.color_count {
                         ui.vertical(|ui| {
-                            color_ui(ui, COLORS[settings.color_order[i].id as usize]);
+                            color_ui(ui, palette_color(settings.color_order[i].id));
 
                             for j in 0..settings.color_count {
-                                ui.add(
-                                    egui::DragValue::new(&mut settings.matrix[i][j])
-                                        .speed(0.01)
-                                        .custom_formatter(|v, _| format!("{:.2}", v)),
-                                );
+                                let (min_radius, max_radius) =
+                                    (settings.min_radius[i][j], settings.max_radius[i][j]);
+                                let drag_value = match view {
+                                    MatrixView::Attraction => {
+                                        egui::DragValue::new(&mut settings.matrix[i][j])
+                                            .speed(0.01)
+                                            .custom_formatter(|v, _| format!("{:.2}", v))
+                                    }
+                                    MatrixView::MinRadius => {
+                                        egui::DragValue::new(&mut settings.min_radius[i][j])
+                                            .range(0.0..=max_radius)
+                                    }
+                                    MatrixView::MaxRadius => {
+                                        egui::DragValue::new(&mut settings.max_radius[i][j])
+                                            .range(min_radius.max(1.)..=largest_radius)
+                                    }
+                                };
+                                ui.add(drag_value);
                             }
                         });
                     }
@@ -158,6 +293,7 @@ pub fn ui(
             }
 
             if ui.button("Randomize attractions").clicked() {
+                settings.reseed();
                 settings.randomize_attractions();
             }
 
@@ -187,10 +323,38 @@ pub fn ui(
                 settings.acceleration_method = method;
             }
 
+            ui.horizontal(|ui| {
+                ui.label("Boundary");
+                let mode = &mut settings.boundary_mode;
+                ui.selectable_value(mode, BoundaryMode::Wrap, "Wrap");
+                ui.selectable_value(mode, BoundaryMode::Reflect, "Reflect");
+                ui.selectable_value(mode, BoundaryMode::Clamp, "Clamp");
+                ui.selectable_value(mode, BoundaryMode::SoftWall, "Soft wall");
+            });
+
+            ui.collapsing("Obstacles", |ui| {
+                // new obstacles are placed in the middle of the view
+                let center = camera_settings.single().1.translation.truncate();
+                let size = settings.max_distance() as f32;
+                obstacle_editor(ui, &mut settings.obstacles, center, size);
+            });
+            ui.collapsing("Force fields", |ui| {
+                let center = camera_settings.single().1.translation.truncate();
+                let size = settings.max_distance() as f32;
+                force_field_editor(ui, &mut settings.force_fields, center, size);
+            });
+            ui.collapsing("Species", |ui| {
+                species_editor(ui, &mut settings);
+            });
+            ui.collapsing("Reactions", |ui| {
+                reaction_editor(ui, &mut settings);
+            });
+
             ui.add_space(10.);
             ui.label("Visual Settings");
 
             if ui.button("Randomize color palette").clicked() {
+                settings.reseed();
                 settings.randomize_colors();
             }
 
@@ -209,11 +373,93 @@ pub fn ui(
             ui.horizontal(|ui| {
                 ui.selectable_value(&mut settings.shape, Shape::Circle, "Circle");
                 ui.selectable_value(&mut settings.shape, Shape::Square, "Square");
+                ui.selectable_value(&mut settings.shape, Shape::Glow, "Glow");
+            });
+            ui.horizontal(|ui| {
+                let blend_mode = &mut settings.blend_mode;
+                ui.selectable_value(blend_mode, BlendMode::Opaque, "Opaque");
+                ui.selectable_value(blend_mode, BlendMode::Alpha, "Alpha");
+                ui.selectable_value(blend_mode, BlendMode::Additive, "Additive");
+            });
+            ui.checkbox(&mut settings.bloom, "Bloom");
+            ui.add_enabled(
+                settings.bloom,
+                egui::Slider::new(&mut settings.bloom_intensity, 0.0..=1.0).text("bloom intensity"),
+            );
+
+            ui.label("Color by");
+            ui.horizontal_wrapped(|ui| {
+                let previous = settings.color_mode;
+                let mode = &mut settings.color_mode;
+                ui.selectable_value(mode, ColorMode::Species, "Species");
+                ui.selectable_value(mode, ColorMode::Speed, "Speed");
+                ui.selectable_value(mode, ColorMode::Heading, "Heading");
+                ui.selectable_value(mode, ColorMode::Neighbours, "Neighbours")
+                    .on_hover_text("Capped by max attractions, like the forces");
+                ui.selectable_value(mode, ColorMode::Force, "Force");
+                if settings.color_mode != previous {
+                    settings.color_max = settings.color_mode.default_max(&settings);
+                }
             });
+            if settings.color_mode == ColorMode::Species {
+                // settings.rgb
+                ui.checkbox(&mut settings.rgb, "RGB");
+                ui.add(egui::Slider::new(&mut settings.rgb_speed, 0.1..=10.).text("RGB speed"));
+            } else {
+                ui.horizontal(|ui| {
+                    let colormap = &mut settings.colormap;
+                    ui.selectable_value(colormap, Colormap::Viridis, "Viridis");
+                    ui.selectable_value(colormap, Colormap::Magma, "Magma");
+                    ui.selectable_value(colormap, Colormap::Inferno, "Inferno");
+                    ui.selectable_value(colormap, Colormap::Plasma, "Plasma");
+                });
+                if settings.color_mode != ColorMode::Heading {
+                    ui.add(
+                        egui::Slider::new(&mut settings.color_max, 0.1..=10_000.)
+                            .logarithmic(true)
+                            .text("colormap max"),
+                    );
+                }
+            }
 
-            // settings.rgb
-            ui.checkbox(&mut settings.rgb, "RGB");
-            ui.add(egui::Slider::new(&mut settings.rgb_speed, 0.1..=10.).text("RGB speed"));
+            ui.checkbox(&mut settings.trails, "Trails");
+            ui.add_enabled(
+                settings.trails,
+                egui::Slider::new(&mut settings.trail_decay, 0.5..=0.999).text("trail decay"),
+            );
+
+            ui.checkbox(&mut heatmap.enabled, "Density heatmap");
+            ui.add_enabled_ui(heatmap.enabled, |ui| {
+                ui.add(egui::Slider::new(&mut heatmap.opacity, 0.0..=1.0).text("heatmap opacity"));
+                ui.add(
+                    egui::Slider::new(&mut heatmap.max_count, 1..=1000)
+                        .logarithmic(true)
+                        .text("heatmap max count"),
+                );
+            });
+
+            // the render world writes the hovered particle, so the lock is only held briefly
+            let mut grid_debug = grid_debug.lock();
+            let cells = settings.cell_count();
+            ui.checkbox(
+                &mut grid_debug.enabled,
+                format!("Grid debug ({}x{} cells)", cells.x, cells.y),
+            );
+            if grid_debug.enabled {
+                match grid_debug.hovered {
+                    Some(hovered) => ui.label(format!(
+                        "particle {} in cell ({}, {}) = {}, counter {}..{} ({} particles)",
+                        hovered.index,
+                        hovered.cell.x,
+                        hovered.cell.y,
+                        hovered.cell_index,
+                        hovered.range.0,
+                        hovered.range.1,
+                        hovered.range.1.saturating_sub(hovered.range.0),
+                    )),
+                    None => ui.label("hover a particle to inspect its cell"),
+                };
+            }
 
             if ui.button("Copy settings to clipboard").clicked() {
                 clipboard.set_contents(&settings.serialize());
@@ -221,17 +467,236 @@ pub fn ui(
 
             if ui.button("Paste settings from clipboard").clicked() {
                 if let Some(s) = clipboard.get_contents() {
-                    if let Some(s) = SimulationSettings::deserialize(&s) {
+                    match SimulationSettings::deserialize(&s) {
+                        Ok(s) => {
+                            *settings = s;
+                            state.error = None;
+                            event_writer.send(ParticleEvent::RandomizeColors);
+                            event_writer.send(ParticleEvent::RandomizePositions);
+                        }
+                        Err(e) => state.error = Some(e),
+                    }
+                }
+            }
+
+            ui.add_space(10.);
+            ui.collapsing("Presets", |ui| {
+                let mut loaded = None;
+                let mut result = Ok(());
+                for (name, _) in BUILTIN_PRESETS {
+                    ui.horizontal(|ui| {
+                        ui.label(*name);
+                        if ui.button("Load").clicked() {
+                            loaded = Some(Presets::load_builtin(name));
+                        }
+                    });
+                }
+
+                ui.separator();
+                for name in presets.names.clone() {
+                    ui.horizontal(|ui| {
+                        ui.label(&name);
+                        if ui.button("Load").clicked() {
+                            loaded = Some(presets.load(&name));
+                        }
+                        if ui
+                            .button("Rename")
+                            .on_hover_text("Renames the preset to the name below")
+                            .clicked()
+                        {
+                            result = presets.rename(&name, &state.preset_name);
+                        }
+                        if ui.button("Delete").clicked() {
+                            result = presets.delete(&name);
+                        }
+                    });
+                }
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut state.preset_name);
+                    if ui.button("Save").clicked() {
+                        result = presets.save(&state.preset_name, &settings);
+                    }
+                });
+
+                match loaded {
+                    Some(Ok(s)) => {
                         *settings = s;
+                        state.error = None;
                         event_writer.send(ParticleEvent::RandomizeColors);
                         event_writer.send(ParticleEvent::RandomizePositions);
                     }
+                    Some(Err(e)) => state.error = Some(e),
+                    None => {}
+                }
+                if let Err(e) = result {
+                    state.error = Some(e);
+                }
+            });
+
+            ui.add_space(10.);
+            ui.label("Snapshot");
+            ui.text_edit_singleline(&mut state.snapshot_path);
+            ui.horizontal(|ui| {
+                if ui.button("Save snapshot").clicked() {
+                    snapshots.request_save(state.snapshot_path.as_str().into());
+                }
+
+                if ui.button("Load snapshot").clicked() {
+                    match Snapshot::load(state.snapshot_path.as_ref()) {
+                        Ok(snapshot) => {
+                            *settings = snapshot.settings;
+                            state.error = None;
+                            snapshots.request_load(snapshot.particles);
+                        }
+                        Err(e) => {
+                            error!("Failed to load snapshot from {}: {e}", state.snapshot_path);
+                            state.error = Some(format!("Failed to load snapshot: {e}"));
+                        }
+                    }
+                }
+            });
+
+            if let Some(error) = state.error.clone() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, error);
+                    if ui.small_button("x").clicked() {
+                        state.error = None;
+                    }
+                });
+            }
+
+            ui.add_space(10.);
+            ui.label("Capture");
+            ui.horizontal(|ui| {
+                ui.label("Directory");
+                let mut directory = capture.directory.display().to_string();
+                if ui.text_edit_singleline(&mut directory).changed() {
+                    capture.directory = directory.into();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Record every");
+                ui.add(egui::DragValue::new(&mut capture.every_nth_frame).range(1..=1000));
+                ui.label("frames with time step");
+                ui.add(
+                    egui::DragValue::new(&mut capture.delta_time)
+                        .speed(0.0001)
+                        .range(0.0001..=1.),
+                );
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Screenshot")
+                    .on_hover_text(format!("{SCREENSHOT_KEY:?}"))
+                    .clicked()
+                {
+                    capture.screenshot_requested = true;
+                }
+                if ui
+                    .button("Start recording")
+                    .on_hover_text(format!("{RECORD_KEY:?} starts and stops recording"))
+                    .clicked()
+                {
+                    capture.start_recording();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Poster");
+                ui.add(egui::DragValue::new(&mut state.poster_size.x).range(1..=32768));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut state.poster_size.y).range(1..=32768));
+                ui.checkbox(&mut state.poster_fit_bounds, "fit bounds");
+            });
+            ui.horizontal(|ui| {
+                let mut custom_size = state.poster_particle_size.is_some();
+                ui.checkbox(&mut custom_size, "Poster particle size");
+                match (custom_size, &mut state.poster_particle_size) {
+                    (true, Some(size)) => {
+                        ui.add(egui::DragValue::new(size).range(0.1..=1000.));
+                    }
+                    (true, size) => *size = Some(settings.particle_size),
+                    (false, size) => *size = None,
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Render poster").clicked() {
+                    posters.request(Poster {
+                        size: state.poster_size,
+                        output: capture
+                            .directory
+                            .join(format!("poster-{}.png", timestamp())),
+                        particle_size: state.poster_particle_size,
+                        fit_bounds: state.poster_fit_bounds,
+                        steps: 0,
+                    });
+                }
+                if posters.pending() > 0 {
+                    ui.label(format!("{} waiting", posters.pending()));
                 }
+            });
+            if let Some(error) = posters.error.clone() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, error);
+                    if ui.small_button("x").clicked() {
+                        posters.error = None;
+                    }
+                });
             }
 
             ui.add_space(10.);
+            ui.label("Brush (ctrl + left mouse)");
+            ui.horizontal(|ui| {
+                let mode = &mut brush.mode;
+                ui.selectable_value(mode, BrushMode::Attract, "Attract");
+                ui.selectable_value(mode, BrushMode::Repel, "Repel");
+                ui.selectable_value(mode, BrushMode::Swirl, "Swirl");
+                ui.selectable_value(mode, BrushMode::Paint, "Paint");
+                ui.selectable_value(mode, BrushMode::Erase, "Erase");
+            });
+            ui.add(
+                egui::Slider::new(&mut brush.radius, 10.0..=2000.0)
+                    .text("brush radius")
+                    .clamp_to_range(false),
+            );
+            ui.add(
+                egui::Slider::new(&mut brush.strength, 0.0..=20_000.0)
+                    .text("brush strength")
+                    .clamp_to_range(false),
+            );
+            if brush.mode == BrushMode::Paint {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("paint color");
+                    for color in 0..settings.color_count {
+                        let c = palette_color(settings.color_order[color].id).to_u8_array();
+                        let swatch = show_color(
+                            ui,
+                            egui::Rgba::from_srgba_unmultiplied(c[0], c[1], c[2], c[3]),
+                            egui::Vec2::new(20., 20.),
+                        )
+                        .interact(egui::Sense::click());
+                        if swatch.clicked() {
+                            brush.color = color as u32;
+                        }
+                        if brush.color == color as u32 {
+                            ui.painter().rect_stroke(
+                                swatch.rect.expand(2.),
+                                0.,
+                                ui.visuals().selection.stroke,
+                            );
+                        }
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut brush.paint_rate, 10.0..=20_000.0)
+                        .text("particles per second")
+                        .clamp_to_range(false),
+                );
+            }
 
-            let mut camera_settings = camera_settings.single_mut();
+            ui.add_space(10.);
+
+            let (mut camera_settings, _) = camera_settings.single_mut();
             ui.label("Camera Settings");
             ui.horizontal(|ui| {
                 ui.label("Pan Speed");
@@ -240,4 +705,345 @@ pub fn ui(
                 ui.add(egui::DragValue::new(&mut camera_settings.scroll_speed).speed(0.01));
             });
         });
+    if !capturing {
+        state.hidden = !open;
+    }
+
+    // the window only keeps the settings consistent for what is edited in it
+    settings.color_count = settings.color_count.max(1);
+    settings.resize_colors();
+    settings.update_max_distance_from_radii();
+}
+
+pub fn toggle_ui(keyboard: Res<ButtonInput<KeyCode>>, mut state: ResMut<UiState>) {
+    if keyboard.just_pressed(TOGGLE_UI_KEY) {
+        state.hidden = !state.hidden;
+    }
+}
+
+/// Adding, editing and removing obstacles. `size` is the size of new obstacles.
+fn obstacle_editor(ui: &mut egui::Ui, obstacles: &mut Vec<Obstacle>, center: Vec2, size: f32) {
+    let vec2 = |ui: &mut egui::Ui, label: &str, value: &mut Vec2| {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            ui.add(egui::DragValue::new(&mut value.x).prefix("x "));
+            ui.add(egui::DragValue::new(&mut value.y).prefix("y "));
+        });
+    };
+
+    ui.horizontal(|ui| {
+        let mut added = None;
+        if ui.button("Add circle").clicked() {
+            added = Some(ObstacleShape::Circle {
+                center,
+                radius: size / 2.,
+            });
+        }
+        if ui.button("Add rectangle").clicked() {
+            added = Some(ObstacleShape::Rectangle {
+                center,
+                size: Vec2::splat(size),
+            });
+        }
+        if ui.button("Add wall").clicked() {
+            added = Some(ObstacleShape::Polyline {
+                points: vec![center - Vec2::X * size, center + Vec2::X * size],
+                thickness: 40.,
+            });
+        }
+        if let Some(shape) = added {
+            obstacles.push(Obstacle {
+                shape,
+                response: ObstacleResponse::Collide,
+            });
+        }
+    });
+
+    let mut removed = None;
+    for (i, obstacle) in obstacles.iter_mut().enumerate() {
+        ui.push_id(i, |ui| {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(match obstacle.shape {
+                    ObstacleShape::Circle { .. } => "Circle",
+                    ObstacleShape::Rectangle { .. } => "Rectangle",
+                    ObstacleShape::Polyline { .. } => "Wall",
+                });
+                let response = &mut obstacle.response;
+                ui.selectable_value(response, ObstacleResponse::Collide, "Collide");
+                ui.selectable_value(response, ObstacleResponse::Repel, "Repel");
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+
+            match &mut obstacle.shape {
+                ObstacleShape::Circle { center, radius } => {
+                    vec2(ui, "center", center);
+                    ui.add(egui::Slider::new(radius, 1.0..=2000.).text("radius"));
+                }
+                ObstacleShape::Rectangle { center, size } => {
+                    vec2(ui, "center", center);
+                    vec2(ui, "size", size);
+                    *size = size.max(Vec2::ONE);
+                }
+                ObstacleShape::Polyline { points, thickness } => {
+                    ui.add(egui::Slider::new(thickness, 1.0..=500.).text("thickness"));
+                    let mut removed_point = None;
+                    let can_remove = points.len() > 2;
+                    for (j, point) in points.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            vec2(ui, &format!("point {j}"), point);
+                            if can_remove && ui.small_button("x").clicked() {
+                                removed_point = Some(j);
+                            }
+                        });
+                    }
+                    if let Some(j) = removed_point {
+                        points.remove(j);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add point").clicked() {
+                            // continuing in the direction of the last segment
+                            let last = points.last().copied().unwrap_or(center);
+                            let direction = match points.len() {
+                                2.. => (last - points[points.len() - 2]).normalize_or(Vec2::X),
+                                _ => Vec2::X,
+                            };
+                            points.push(last + direction * size);
+                        }
+                        // connecting the last point to the first one makes a container
+                        if ui.button("Close").clicked() && points.first() != points.last() {
+                            points.push(points[0]);
+                        }
+                    });
+                }
+            }
+        });
+    }
+    if let Some(i) = removed {
+        obstacles.remove(i);
+    }
+}
+
+/// Adding, editing and removing force fields. `size` is the size of new fields.
+fn force_field_editor(
+    ui: &mut egui::Ui,
+    force_fields: &mut Vec<ForceField>,
+    center: Vec2,
+    size: f32,
+) {
+    ui.horizontal_wrapped(|ui| {
+        if ui.button("Add gravity").clicked() {
+            force_fields.push(ForceField::Gravity {
+                acceleration: Vec2::new(0., -100.),
+            });
+        }
+        if ui.button("Add attractor").clicked() {
+            force_fields.push(ForceField::Attractor {
+                center,
+                strength: 300.,
+                radius: 2. * size,
+            });
+        }
+        if ui.button("Add vortex").clicked() {
+            force_fields.push(ForceField::Vortex {
+                center,
+                strength: 300.,
+                radius: 2. * size,
+            });
+        }
+        if ui.button("Add noise flow").clicked() {
+            force_fields.push(ForceField::Noise {
+                strength: 100.,
+                scale: 4. * size,
+                speed: 0.1,
+            });
+        }
+    });
+
+    let mut removed = None;
+    for (i, field) in force_fields.iter_mut().enumerate() {
+        ui.push_id(i, |ui| {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(match field {
+                    ForceField::Gravity { .. } => "Gravity",
+                    ForceField::Attractor { .. } => "Attractor",
+                    ForceField::Vortex { .. } => "Vortex",
+                    ForceField::Noise { .. } => "Noise flow",
+                });
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+
+            match field {
+                ForceField::Gravity { acceleration } => {
+                    ui.horizontal(|ui| {
+                        ui.label("acceleration");
+                        ui.add(egui::DragValue::new(&mut acceleration.x).prefix("x "));
+                        ui.add(egui::DragValue::new(&mut acceleration.y).prefix("y "));
+                    });
+                }
+                ForceField::Attractor {
+                    center,
+                    strength,
+                    radius,
+                }
+                | ForceField::Vortex {
+                    center,
+                    strength,
+                    radius,
+                } => {
+                    ui.horizontal(|ui| {
+                        ui.label("center");
+                        ui.add(egui::DragValue::new(&mut center.x).prefix("x "));
+                        ui.add(egui::DragValue::new(&mut center.y).prefix("y "));
+                    });
+                    ui.add(
+                        egui::Slider::new(strength, -2000.0..=2000.)
+                            .text("strength")
+                            .clamp_to_range(false),
+                    );
+                    ui.add(egui::Slider::new(radius, 1.0..=10_000.).text("radius"));
+                }
+                ForceField::Noise {
+                    strength,
+                    scale,
+                    speed,
+                } => {
+                    ui.add(
+                        egui::Slider::new(strength, 0.0..=2000.)
+                            .text("strength")
+                            .clamp_to_range(false),
+                    );
+                    ui.add(
+                        egui::Slider::new(scale, 10.0..=20_000.)
+                            .logarithmic(true)
+                            .text("scale"),
+                    );
+                    ui.add(egui::Slider::new(speed, 0.0..=2.).text("speed"));
+                }
+            }
+        });
+    }
+    if let Some(i) = removed {
+        force_fields.remove(i);
+    }
+}
+
+/// Per species parameters, one row per color
+fn species_editor(ui: &mut egui::Ui, settings: &mut SimulationSettings) {
+    ui.horizontal(|ui| {
+        // starting from the global parameters if there are no species for the colors yet,
+        // an edited table is kept when turning this off and on again
+        if ui
+            .checkbox(&mut settings.per_species, "per species parameters")
+            .changed()
+            && settings.per_species
+            && settings.species.len() < settings.color_count
+        {
+            settings.reset_species();
+        }
+        if settings.per_species && ui.button("Reset species").clicked() {
+            settings.reset_species();
+        }
+    });
+    if !settings.per_species {
+        return;
+    }
+
+    egui::Grid::new("species").striped(true).show(ui, |ui| {
+        ui.label("");
+        ui.label("mass");
+        ui.label("half life");
+        ui.label("max velocity");
+        ui.label("size");
+        ui.end_row();
+
+        for color in 0..settings.color_count {
+            let c = palette_color(settings.color_order[color].id).to_u8_array();
+            show_color(
+                ui,
+                egui::Rgba::from_srgba_unmultiplied(c[0], c[1], c[2], c[3]),
+                egui::Vec2::new(20., 20.),
+            );
+            let species = &mut settings.species[color];
+            ui.add(
+                egui::DragValue::new(&mut species.mass)
+                    .speed(0.01)
+                    .range(0.01..=100.0),
+            );
+            ui.add(
+                egui::DragValue::new(&mut species.velocity_half_life)
+                    .speed(0.001)
+                    .range(0.001..=10.0),
+            );
+            ui.add(egui::DragValue::new(&mut species.max_velocity).range(0.0..=100_000.0));
+            ui.add(
+                egui::DragValue::new(&mut species.size)
+                    .speed(0.01)
+                    .range(0.0..=20.0),
+            );
+            ui.end_row();
+        }
+    });
+}
+
+/// Reactions between colors, a particle of the first color turns into the last one
+/// near a particle of the middle one
+fn reaction_editor(ui: &mut egui::Ui, settings: &mut SimulationSettings) {
+    let max_distance = settings.max_distance() as f32;
+    let max_color = (settings.color_count as u32).saturating_sub(1);
+    let color_order = &settings.color_order;
+    let color_slot = |ui: &mut egui::Ui, color: &mut u32| {
+        if let Some(id) = color_order.get(*color as usize) {
+            let c = palette_color(id.id).to_u8_array();
+            show_color(
+                ui,
+                egui::Rgba::from_srgba_unmultiplied(c[0], c[1], c[2], c[3]),
+                egui::Vec2::new(20., 20.),
+            );
+        }
+        ui.add(egui::DragValue::new(color).range(0..=max_color));
+    };
+
+    let reactions = &mut settings.reactions;
+    ui.add_enabled_ui(reactions.len() < MAX_REACTIONS, |ui| {
+        if ui.button("Add reaction").clicked() {
+            reactions.push(Reaction {
+                color: 0,
+                catalyst: 1.min(max_color),
+                product: 1.min(max_color),
+                radius: max_distance / 2.,
+                probability: 0.5,
+            });
+        }
+    });
+
+    let mut removed = None;
+    for (i, reaction) in reactions.iter_mut().enumerate() {
+        ui.push_id(i, |ui| {
+            ui.separator();
+            ui.horizontal(|ui| {
+                color_slot(ui, &mut reaction.color);
+                ui.label("near");
+                color_slot(ui, &mut reaction.catalyst);
+                ui.label("becomes");
+                color_slot(ui, &mut reaction.product);
+                if ui.button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+            ui.add(egui::Slider::new(&mut reaction.radius, 1.0..=max_distance).text("radius"));
+            ui.add(
+                egui::Slider::new(&mut reaction.probability, 0.0..=1.0)
+                    .text("probability per second"),
+            );
+        });
+    }
+    if let Some(i) = removed {
+        reactions.remove(i);
+    }
 }